@@ -0,0 +1,418 @@
+use std::env;
+use std::fs;
+use std::path::Path;
+use std::process::ExitCode;
+
+use podaemon::control::{
+    self,
+    rpc::{RpcRequest, RpcResponse},
+};
+use podaemon::player::{
+    query::{Entry, FeedInfo, Status},
+    state::{Episode, Playable},
+    stats::{duration_string, ExportFormat, Stats, Total},
+    Cmd, Response, UpdateArgs,
+};
+use serde_json::Value;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+
+const USAGE: &str = "\
+usage: podctl [--host HOST:PORT] [--token TOKEN] [--json] COMMAND [ARGS]
+
+Connects to the unix socket of a running podaemon (SOCKET_PATH or
+$XDG_RUNTIME_DIR/podaemon.sock), or to HOST:PORT when --host or
+PODCTL_HOST is given. --token or PODCTL_TOKEN authenticates the
+connection if the daemon requires it.
+
+commands:
+  play | pause | toggle | next | prev | shutdown
+  queue PATH|URI           queue a file or stream, paths become file:// uris
+  play-now PATH|URI        play a file or stream right away
+  seek POS                 absolute position, as SECONDS or [HH:]MM:SS
+  seek +SECS | -SECS       relative seek
+  delete-queue INDEX | delete-recent INDEX
+  dequeue PATH|URI         take a file or stream out of the queue
+  subscribe URL
+  unsubscribe URL [--purge]
+                           with --purge also forget the episodes of the feed
+  refresh [URL]            fetch one feed, or all of them, right away
+  import-opml FILE         subscribe to the feeds listed in FILE
+  export-opml FILE         write the subscriptions to FILE
+  update URI PROGRESS LENGTH TIME
+  status | list-queue | list-recent | list-feeds | list-episodes
+  get URI                  stored progress and metadata of URI
+  mark-played URI | mark-unplayed URI
+  mark-seen [URL]          clear the new marker of the episodes of one
+                           feed, or of all of them
+  stats                    listening time per podcast, day and week
+  export-stats FILE        write all listening sessions to FILE, as CSV
+                           if it ends in .csv and as JSON otherwise
+  watch                    print events as JSON lines until the daemon exits";
+
+struct Options {
+    host: Option<String>,
+    token: Option<String>,
+    json: bool,
+}
+
+#[tokio::main(flavor = "current_thread")]
+async fn main() -> ExitCode {
+    let mut options = Options {
+        host: env::var("PODCTL_HOST").ok(),
+        token: env::var("PODCTL_TOKEN").ok(),
+        json: false,
+    };
+    let mut args = Vec::new();
+    let mut argv = env::args().skip(1);
+    while let Some(arg) = argv.next() {
+        match arg.as_str() {
+            "--host" => options.host = argv.next(),
+            "--token" => options.token = argv.next(),
+            "--json" => options.json = true,
+            "-h" | "--help" => {
+                println!("{USAGE}");
+                return ExitCode::SUCCESS;
+            }
+            _ => args.push(arg),
+        }
+    }
+
+    if args.first().map(String::as_str) == Some("watch") {
+        return match watch(&options).await {
+            Ok(()) => ExitCode::SUCCESS,
+            Err(err) => {
+                eprintln!("podctl: {err}");
+                ExitCode::FAILURE
+            }
+        };
+    }
+
+    let (cmd, display) = match parse_args(&args) {
+        Ok(parsed) => parsed,
+        Err(err) => {
+            eprintln!("podctl: {err}\n\n{USAGE}");
+            return ExitCode::from(2);
+        }
+    };
+
+    let response = match send(&options, &cmd).await {
+        Ok(response) => response,
+        Err(err) => {
+            eprintln!("podctl: {err}");
+            return ExitCode::FAILURE;
+        }
+    };
+
+    match response {
+        Ok(value) => {
+            if let Some(value) = value {
+                if let Display::Save(path) = &display {
+                    if let Err(err) = save(path, value) {
+                        eprintln!("podctl: {path}: {err}");
+                        return ExitCode::FAILURE;
+                    }
+                } else if options.json {
+                    println!("{value:#}");
+                } else if let Err(err) = display.print(value) {
+                    eprintln!("podctl: unexpected response: {err}");
+                    return ExitCode::FAILURE;
+                }
+            }
+            ExitCode::SUCCESS
+        }
+        Err(err) => {
+            eprintln!("podctl: {err}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+/// The command and how to show its answer.
+fn parse_args(args: &[String]) -> Result<(Cmd, Display), String> {
+    let Some((name, args)) = args.split_first() else {
+        return Err("no command given".into());
+    };
+    let arg = |i: usize| {
+        args.get(i)
+            .map(String::as_str)
+            .ok_or_else(|| format!("{name}: missing argument"))
+    };
+    let number = |i: usize| {
+        let arg = arg(i)?;
+        arg.parse::<u64>()
+            .map_err(|_| format!("{name}: not a number: {arg}"))
+    };
+    let index = |i: usize| number(i).map(|n| n as usize);
+
+    let cmd = match name.replace('-', "_").as_str() {
+        "play" => Cmd::Play,
+        "pause" => Cmd::Pause,
+        "toggle" | "play_pause" => Cmd::PlayPause,
+        "next" => Cmd::Next,
+        "prev" => Cmd::Prev,
+        "shutdown" => Cmd::Shutdown,
+        "queue" => Cmd::Queue(to_uri(arg(0)?)?),
+        "play_now" => Cmd::PlayNow(to_uri(arg(0)?)?),
+        "seek" => parse_seek(arg(0)?)?,
+        "seek_relative" => {
+            let delta = arg(0)?;
+            let invalid = |_| format!("{name}: not a number: {delta}");
+            Cmd::SeekRelative(delta.parse().map_err(invalid)?)
+        }
+        "delete_queue" => Cmd::DeleteQueue(index(0)?),
+        "delete_recent" => Cmd::DeleteRecent(index(0)?),
+        "dequeue" => Cmd::Dequeue(to_uri(arg(0)?)?),
+        "subscribe" => Cmd::Subscribe(arg(0)?.to_string()),
+        "unsubscribe" => match args.get(1).map(String::as_str) {
+            None => Cmd::Unsubscribe(arg(0)?.to_string()),
+            Some("--purge") => Cmd::Purge(arg(0)?.to_string()),
+            Some(other) => return Err(format!("{name}: unknown option: {other}")),
+        },
+        "refresh" => Cmd::Refresh(args.first().cloned()),
+        "mark_seen" => Cmd::MarkSeen(args.first().cloned()),
+        "import_opml" => {
+            let path = arg(0)?;
+            let xml = fs::read_to_string(path).map_err(|err| format!("{path}: {err}"))?;
+            Cmd::ImportOpml(xml)
+        }
+        "export_opml" => return Ok((Cmd::ExportOpml, Display::Save(arg(0)?.into()))),
+        "update" => Cmd::Update(UpdateArgs(
+            arg(0)?.to_string(),
+            Playable {
+                progress: Some(number(1)?),
+                length: Some(number(2)?),
+                updated: Some(number(3)?),
+                ..Playable::default()
+            },
+        )),
+        "status" => Cmd::Status,
+        "list_queue" => Cmd::ListQueue,
+        "list_recent" => Cmd::ListRecent,
+        "list_feeds" => Cmd::ListFeeds,
+        "list_episodes" => Cmd::ListEpisodes,
+        "get" | "get_playable" => Cmd::GetPlayable(arg(0)?.to_string()),
+        "mark_played" => Cmd::MarkPlayed(arg(0)?.to_string()),
+        "mark_unplayed" => Cmd::MarkUnplayed(arg(0)?.to_string()),
+        "stats" => Cmd::Stats,
+        "export_stats" => {
+            let path = arg(0)?;
+            let format = if path.ends_with(".csv") {
+                ExportFormat::Csv
+            } else {
+                ExportFormat::Json
+            };
+            return Ok((Cmd::ExportStats(format), Display::Save(path.into())));
+        }
+        _ => return Err(format!("unknown command: {name}")),
+    };
+    let display = Display::of(&cmd);
+    Ok((cmd, display))
+}
+
+/// `+30` and `-30` seek relative to the current position, anything else is
+/// an absolute position in seconds or `[HH:]MM:SS`.
+fn parse_seek(arg: &str) -> Result<Cmd, String> {
+    let invalid = || format!("seek: invalid position: {arg}");
+    if arg.starts_with(['+', '-']) {
+        return arg.parse().map(Cmd::SeekRelative).map_err(|_| invalid());
+    }
+    let mut seconds = 0;
+    for part in arg.split(':') {
+        seconds = seconds * 60 + part.parse::<u64>().map_err(|_| invalid())?;
+    }
+    Ok(Cmd::Seek(seconds))
+}
+
+/// Turns a path into a `file://` uri like the file prompt of the TUI does,
+/// relative paths are resolved against the working directory.
+fn to_uri(arg: &str) -> Result<String, String> {
+    if arg.contains("://") {
+        return Ok(arg.to_string());
+    }
+    let path = Path::new(arg)
+        .canonicalize()
+        .map_err(|err| format!("{arg}: {err}"))?;
+    Ok(format!("file://{}", path.display()))
+}
+
+async fn send(options: &Options, cmd: &Cmd) -> Result<Response, String> {
+    let request = RpcRequest::new(cmd, Some(1.into())).map_err(|err| err.to_string())?;
+    let mut line = serde_json::to_string(&request).map_err(|err| err.to_string())?;
+    line.push('\n');
+
+    let stream = control::connect(options.host.as_deref(), options.token.as_deref()).await?;
+    let (reader, mut writer) = tokio::io::split(stream);
+    let mut reply = String::new();
+    async {
+        writer.write_all(line.as_bytes()).await?;
+        BufReader::new(reader).read_line(&mut reply).await
+    }
+    .await
+    .map_err(|err| format!("connection error: {err}"))?;
+
+    let response: RpcResponse =
+        serde_json::from_str(&reply).map_err(|err| format!("invalid response: {err}"))?;
+    Ok(response.into_response())
+}
+
+/// Prints every event pushed by the daemon, one JSON object per line.
+async fn watch(options: &Options) -> Result<(), String> {
+    let stream = control::connect(options.host.as_deref(), options.token.as_deref()).await?;
+    let (reader, mut writer) = tokio::io::split(stream);
+    let mut lines = BufReader::new(reader).lines();
+    let io_err = |err| format!("connection error: {err}");
+
+    writer.write_all(b"watch\n").await.map_err(io_err)?;
+    match lines.next_line().await.map_err(io_err)? {
+        Some(ack) if ack == "ok" => {}
+        Some(err) => return Err(err),
+        None => return Err("connection closed".into()),
+    }
+    while let Some(line) = lines.next_line().await.map_err(io_err)? {
+        println!("{line}");
+    }
+    Ok(())
+}
+
+/// How a query result is shown to humans.
+enum Display {
+    Status,
+    Entries,
+    Feeds,
+    Episodes,
+    Playable,
+    Stats,
+    Raw,
+    /// Written to the file instead, strings as they are.
+    Save(String),
+}
+
+impl Display {
+    fn of(cmd: &Cmd) -> Self {
+        match cmd {
+            Cmd::Status => Display::Status,
+            Cmd::ListQueue | Cmd::ListRecent => Display::Entries,
+            Cmd::ListFeeds => Display::Feeds,
+            Cmd::ListEpisodes => Display::Episodes,
+            Cmd::GetPlayable(_) => Display::Playable,
+            Cmd::Stats => Display::Stats,
+            _ => Display::Raw,
+        }
+    }
+
+    fn print(self, value: Value) -> Result<(), serde_json::Error> {
+        match self {
+            Display::Status => print_status(serde_json::from_value(value)?),
+            Display::Entries => {
+                let entries: Vec<Entry> = serde_json::from_value(value)?;
+                for (i, entry) in entries.iter().enumerate() {
+                    let playable = entry.playable.as_ref();
+                    let title = playable.and_then(|p| p.title.as_deref());
+                    let album = playable.and_then(|p| p.album.as_deref());
+                    let progress = playable.map(Playable::progress_string);
+                    println!(
+                        "{i:3}: {}{} [{}]",
+                        album.map(|a| format!("{a} - ")).unwrap_or_default(),
+                        title.unwrap_or(&entry.uri),
+                        progress.as_deref().unwrap_or("n/a"),
+                    );
+                }
+            }
+            Display::Feeds => {
+                let feeds: Vec<FeedInfo> = serde_json::from_value(value)?;
+                for (i, feed) in feeds.iter().enumerate() {
+                    let new = match feed.new_episodes {
+                        0 => String::new(),
+                        n => format!(", {n} new"),
+                    };
+                    println!(
+                        "{i:3}: {} ({} episodes{new})\n     {}",
+                        feed.title.as_deref().unwrap_or("n/a"),
+                        feed.episodes,
+                        feed.uri
+                    );
+                }
+            }
+            Display::Episodes => {
+                let episodes: Vec<Episode> = serde_json::from_value(value)?;
+                for Episode {
+                    channel_title,
+                    item,
+                } in episodes
+                {
+                    println!(
+                        "{} | {} | {}\n     {}",
+                        item.pub_date().unwrap_or("n/a"),
+                        channel_title,
+                        item.title().unwrap_or("n/a"),
+                        item.enclosure().map(|e| e.url()).unwrap_or("no enclosure"),
+                    );
+                }
+            }
+            Display::Playable => {
+                let playable: Playable = serde_json::from_value(value)?;
+                println!("title:    {}", playable.title.as_deref().unwrap_or("n/a"));
+                println!("album:    {}", playable.album.as_deref().unwrap_or("n/a"));
+                println!("progress: {}", playable.progress_string());
+                let status = serde_json::to_value(playable.status())?;
+                println!("status:   {}", status.as_str().unwrap_or_default());
+                println!("played:   {} times", playable.play_count);
+            }
+            Display::Stats => print_stats(serde_json::from_value(value)?),
+            Display::Raw | Display::Save(_) => println!("{value:#}"),
+        }
+        Ok(())
+    }
+}
+
+fn save(path: &str, value: Value) -> std::io::Result<()> {
+    match value {
+        Value::String(contents) => fs::write(path, contents),
+        value => fs::write(path, format!("{value:#}\n")),
+    }
+}
+
+fn print_status(status: Status) {
+    let title = status.title.as_deref().or(status.uri.as_deref());
+    println!("{}: {}", status.state, title.unwrap_or("nothing loaded"));
+    if let Some(album) = &status.album {
+        println!("  {album}");
+    }
+    println!("  {} / {}", hms(status.position), hms(status.duration));
+    println!("  {} queued", status.queue_len);
+    if let Some(warning) = &status.warning {
+        println!("warning: {warning}");
+    }
+}
+
+fn print_stats(stats: Stats) {
+    println!(
+        "listened {} in {} sessions, {} episodes finished",
+        duration_string(stats.seconds),
+        stats.sessions,
+        stats.finished
+    );
+    if let Some(completion) = stats.completion {
+        println!("average completion {:.0}%", completion * 100.0);
+    }
+    for (title, totals) in [
+        ("podcasts", &stats.per_feed),
+        ("last days", &stats.per_day),
+        ("last weeks", &stats.per_week),
+    ] {
+        if totals.is_empty() {
+            continue;
+        }
+        println!("\n{title}:");
+        for Total { name, seconds } in totals.iter().take(10) {
+            println!("  {:>8}  {name}", duration_string(*seconds));
+        }
+    }
+}
+
+fn hms(seconds: Option<u64>) -> String {
+    match seconds {
+        Some(s) => format!("{}:{:02}:{:02}", s / 3600, s / 60 % 60, s % 60),
+        None => "n/a".into(),
+    }
+}
@@ -0,0 +1,401 @@
+use std::{
+    env, fs, future, io,
+    os::unix::fs::{DirBuilderExt, MetadataExt},
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use tokio::{
+    io::{AsyncBufReadExt, AsyncRead, AsyncWrite, AsyncWriteExt, BufReader},
+    net::{TcpStream, UnixListener, UnixStream},
+    select,
+    sync::{
+        broadcast::{self, error::RecvError},
+        mpsc::Sender,
+    },
+};
+
+use crate::{
+    events::{self, Event},
+    logln,
+    player::{self, Cmd, Request, Response},
+};
+
+use self::auth::{Access, Tokens};
+use self::rpc::{Outcome, RpcError, RpcResponse};
+
+pub mod auth;
+pub mod rpc;
+
+/// Location of the unix control socket: `$SOCKET_PATH` if set, otherwise
+/// `podaemon.sock` in `$XDG_RUNTIME_DIR` (or in a private dir in the temp dir
+/// as a last resort).
+pub fn socket_path() -> PathBuf {
+    if let Ok(path) = env::var("SOCKET_PATH") {
+        return PathBuf::from(path);
+    }
+    match env::var("XDG_RUNTIME_DIR") {
+        Ok(dir) => PathBuf::from(dir).join("podaemon.sock"),
+        Err(_) => fallback_dir().join("podaemon.sock"),
+    }
+}
+
+fn fallback_dir() -> PathBuf {
+    let user = env::var("USER").unwrap_or_default();
+    env::temp_dir().join(format!("podaemon-{user}"))
+}
+
+/// Creates `dir` accessible to us only, or checks that it already is. Anyone
+/// could have created a dir of that name in the temp dir before us.
+fn private_dir(dir: &Path) -> Result<(), String> {
+    match fs::DirBuilder::new().mode(0o700).create(dir) {
+        Ok(()) => return Ok(()),
+        Err(err) if err.kind() == io::ErrorKind::AlreadyExists => {}
+        Err(err) => return Err(err.to_string()),
+    }
+    let meta = fs::symlink_metadata(dir).map_err(|err| err.to_string())?;
+    // SAFETY: getuid has no preconditions and never fails
+    let uid = unsafe { libc::getuid() };
+    if !meta.is_dir() || meta.uid() != uid || meta.mode() & 0o077 != 0 {
+        return Err("it is not a dir that only we can access".into());
+    }
+    Ok(())
+}
+
+/// Binds the unix control socket at `path`, readable and writable by the
+/// owner only. Gives up if another instance is already listening there.
+pub async fn bind_unix(path: &Path) -> Option<UnixListener> {
+    if path.parent() == Some(fallback_dir().as_path()) {
+        if let Err(err) = private_dir(&fallback_dir()) {
+            logln!("not binding {}: {err}", path.display());
+            return None;
+        }
+    }
+    if path.exists() {
+        if UnixStream::connect(path).await.is_ok() {
+            logln!("control socket {} is already in use", path.display());
+            return None;
+        }
+        // left behind by an instance that did not shut down cleanly
+        if let Err(err) = fs::remove_file(path) {
+            logln!("failed to remove stale socket {}: {err}", path.display());
+            return None;
+        }
+    }
+
+    // created with the right permissions, changing them after binding would
+    // leave a moment in which others can connect
+    // SAFETY: umask has no preconditions and never fails
+    let umask = unsafe { libc::umask(0o177) };
+    let bound = UnixListener::bind(path);
+    // SAFETY: as above
+    unsafe { libc::umask(umask) };
+    let listener = match bound {
+        Ok(listener) => listener,
+        Err(err) => {
+            logln!("failed to bind {}: {err}", path.display());
+            return None;
+        }
+    };
+    logln!("listening on: {}", path.display());
+    Some(listener)
+}
+
+pub async fn listen_unix(queue: Sender<Request>, listener: UnixListener) {
+    while let Ok((socket, _addr)) = listener.accept().await {
+        // whoever can open the socket is trusted, its permissions see to that
+        tokio::spawn(serve(socket, queue.clone(), "unix socket".into(), None));
+    }
+    logln!("unix listener ended");
+}
+
+/// Serves one control connection until the peer closes it. Lines starting with
+/// `{` are JSON-RPC requests and get JSON-RPC responses. Any other line is
+/// parsed as a plain command and answered with a line of its own, `ok`,
+/// `error: <reason>` or, for queries, a JSON document.
+///
+/// After `watch` every [`Event`] is pushed to the connection as a JSON line
+/// as well, until `unwatch`.
+///
+/// With `tokens` the first line has to be `auth <token>`, the token decides
+/// which commands the connection may use. Without them everything is allowed
+/// and `auth` is simply acknowledged.
+pub async fn serve<S>(stream: S, queue: Sender<Request>, peer: String, tokens: Option<Arc<Tokens>>)
+where
+    S: AsyncRead + AsyncWrite,
+{
+    let (reader, mut writer) = tokio::io::split(stream);
+    let mut lines = BufReader::new(reader).lines();
+    let mut events = None;
+    let mut access = tokens.is_none().then_some(Access::All);
+    loop {
+        let line = select! {
+            line = lines.next_line() => match line {
+                Ok(Some(line)) => line,
+                Ok(None) => break,
+                Err(err) => {
+                    logln!("control connection error: {err}");
+                    break;
+                }
+            },
+            event = next_event(&mut events) => match serde_json::to_string(&event) {
+                Ok(json) => {
+                    if let Err(err) = writer.write_all(format!("{json}\n").as_bytes()).await {
+                        logln!("control connection error: {err}");
+                        break;
+                    }
+                    continue;
+                }
+                Err(err) => {
+                    logln!("failed to serialize event: {err}");
+                    continue;
+                }
+            },
+        };
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+
+        let reply = if let Some(token) = line.strip_prefix("auth ") {
+            match tokens.as_ref().map(|tokens| tokens.check(token.trim())) {
+                None => "ok\n".to_string(),
+                Some(Some(granted)) => {
+                    access = Some(granted);
+                    "ok\n".to_string()
+                }
+                Some(None) => {
+                    logln!("rejected control connection from {peer}: invalid token");
+                    let _ = writer.write_all(b"error: invalid token\n").await;
+                    break;
+                }
+            }
+        } else if let Some(access) = &access {
+            if line.starts_with('{') {
+                match handle_rpc(line, &queue, access, &peer, &mut events).await {
+                    Some(response) => response,
+                    None => continue,
+                }
+            } else {
+                handle_text(line, &queue, access, &peer, &mut events).await
+            }
+        } else {
+            logln!("rejected control connection from {peer}: not authenticated");
+            let _ = writer.write_all(b"error: authentication required\n").await;
+            break;
+        };
+        if let Err(err) = writer.write_all(reply.as_bytes()).await {
+            logln!("control connection error: {err}");
+            break;
+        }
+    }
+}
+
+/// Commands that only concern the connection itself and never reach the
+/// player, returns whether `name` was one of them.
+fn session_cmd(name: &str, events: &mut Option<broadcast::Receiver<Event>>) -> bool {
+    match name {
+        "watch" => *events = Some(events::subscribe()),
+        "unwatch" => *events = None,
+        _ => return false,
+    }
+    true
+}
+
+async fn next_event(events: &mut Option<broadcast::Receiver<Event>>) -> Event {
+    if let Some(rx) = events {
+        loop {
+            match rx.recv().await {
+                Ok(event) => return event,
+                Err(RecvError::Lagged(n)) => logln!("watcher missed {n} events"),
+                Err(RecvError::Closed) => break,
+            }
+        }
+    }
+    future::pending().await
+}
+
+/// Refuses commands the connection has no access to.
+fn permit(access: &Access, name: &str, peer: &str) -> Result<(), String> {
+    if access.allows(name) {
+        return Ok(());
+    }
+    logln!("refused {name} from {peer}");
+    Err(format!("not permitted: {name}"))
+}
+
+/// Answers a line in the plain text syntax of [`player::parse_cmd`].
+async fn handle_text(
+    line: &str,
+    queue: &Sender<Request>,
+    access: &Access,
+    peer: &str,
+    events: &mut Option<broadcast::Receiver<Event>>,
+) -> String {
+    let name = line.split('(').next().unwrap_or(line);
+    let response = if let Err(err) = permit(access, name, peer) {
+        Err(err)
+    } else if session_cmd(line, events) {
+        Ok(None)
+    } else {
+        match player::try_parse_cmd(line) {
+            Ok(cmd) => execute(cmd, queue).await,
+            Err(err) => {
+                logln!("parse failed: {err}");
+                Err(err)
+            }
+        }
+    };
+    match response {
+        Ok(None) => "ok\n".to_string(),
+        Ok(Some(value)) => format!("{value}\n"),
+        Err(err) => format!("error: {err}\n"),
+    }
+}
+
+/// Answers a JSON-RPC request, notifications are executed without a reply.
+async fn handle_rpc(
+    line: &str,
+    queue: &Sender<Request>,
+    access: &Access,
+    peer: &str,
+    events: &mut Option<broadcast::Receiver<Event>>,
+) -> Option<String> {
+    let (notification, response) = match rpc::parse_request(line) {
+        Ok(request) => {
+            let id = request.id.clone();
+            let response = match permit(access, &request.method, peer) {
+                Err(err) => {
+                    let err = RpcError::new(rpc::FORBIDDEN, err);
+                    RpcResponse::new(id, Outcome::Error(err))
+                }
+                Ok(()) if session_cmd(&request.method, events) => {
+                    RpcResponse::from_response(id, Ok(None))
+                }
+                Ok(()) => match request.to_cmd() {
+                    Ok(cmd) => RpcResponse::from_response(id, execute(cmd, queue).await),
+                    Err(err) => RpcResponse::new(id, Outcome::Error(err)),
+                },
+            };
+            (request.id.is_none(), response)
+        }
+        // requests that can not be told apart from notifications get a null id
+        Err(response) => (false, response),
+    };
+    if let Outcome::Error(err) = &response.outcome {
+        logln!("rpc error: {}", err.message);
+    }
+    if notification {
+        return None;
+    }
+    match serde_json::to_string(&response) {
+        Ok(mut json) => {
+            json.push('\n');
+            Some(json)
+        }
+        Err(err) => {
+            logln!("failed to serialize rpc response: {err}");
+            None
+        }
+    }
+}
+
+pub trait Connection: AsyncRead + AsyncWrite + Unpin + Send {}
+impl<T: AsyncRead + AsyncWrite + Unpin + Send> Connection for T {}
+
+/// Connects to a running daemon, to `host` over TCP if given and to the unix
+/// socket otherwise. With a `token` the connection is authenticated first.
+pub async fn connect(
+    host: Option<&str>,
+    token: Option<&str>,
+) -> Result<Box<dyn Connection>, String> {
+    let stream: Box<dyn Connection> = if let Some(host) = host {
+        let stream = TcpStream::connect(host)
+            .await
+            .map_err(|err| format!("failed to connect to {host}: {err}"))?;
+        Box::new(stream)
+    } else {
+        let path = socket_path();
+        let stream = UnixStream::connect(&path)
+            .await
+            .map_err(|err| format!("failed to connect to {}: {err}", path.display()))?;
+        Box::new(stream)
+    };
+    let Some(token) = token else {
+        return Ok(stream);
+    };
+
+    // the buffered reader keeps whatever follows the acknowledgement
+    let mut stream = BufReader::new(stream);
+    let mut ack = String::new();
+    async {
+        stream
+            .write_all(format!("auth {token}\n").as_bytes())
+            .await?;
+        stream.read_line(&mut ack).await
+    }
+    .await
+    .map_err(|err| format!("connection error: {err}"))?;
+    match ack.trim_end() {
+        "ok" => Ok(Box::new(stream)),
+        "" => Err("connection closed".into()),
+        err => Err(err.to_string()),
+    }
+}
+
+/// Hands `cmd` to the player and waits for the outcome.
+pub async fn execute(cmd: Cmd, queue: &Sender<Request>) -> Response {
+    let (request, reply) = Request::new(cmd);
+    if queue.send(request).await.is_err() {
+        return Err("player is not running".into());
+    }
+    reply
+        .await
+        .unwrap_or_else(|_| Err("player dropped the command".into()))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::os::unix::fs::PermissionsExt;
+
+    use tokio::sync::mpsc;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn notifications_get_no_reply() {
+        let (queue, _rx) = mpsc::channel(1);
+        let reply = |line: &'static str| {
+            let queue = queue.clone();
+            async move { handle_rpc(line, &queue, &Access::All, "test", &mut None).await }
+        };
+        assert_eq!(
+            reply(r#"{"jsonrpc": "2.0", "method": "dance"}"#).await,
+            None
+        );
+        assert_eq!(
+            reply(r#"{"jsonrpc": "2.0", "method": "seek", "params": "soon"}"#).await,
+            None
+        );
+        let error = reply(r#"{"jsonrpc": "2.0", "id": 1, "method": "dance"}"#).await;
+        assert!(error.unwrap().contains(r#""id":1"#));
+        // without a method it is not a notification but an invalid request
+        let invalid = reply(r#"{"jsonrpc": "2.0"}"#).await;
+        assert!(invalid.unwrap().contains(r#""id":null"#));
+        let garbled = reply("{").await;
+        assert!(garbled.unwrap().contains(r#""id":null"#));
+    }
+
+    #[test]
+    fn private_dir_only() {
+        let dir = env::temp_dir().join(format!("podaemon-control-{}", std::process::id()));
+        private_dir(&dir).unwrap();
+        assert_eq!(fs::metadata(&dir).unwrap().mode() & 0o777, 0o700);
+        private_dir(&dir).unwrap();
+
+        // one that others can get into is refused
+        fs::set_permissions(&dir, fs::Permissions::from_mode(0o755)).unwrap();
+        assert!(private_dir(&dir).is_err());
+        fs::remove_dir(dir).unwrap();
+    }
+}
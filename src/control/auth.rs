@@ -0,0 +1,122 @@
+//! Shared secret tokens for remote control connections.
+//!
+//! `TOKENS` holds whitespace separated entries, either a bare `token` that
+//! grants every command or `token:cmd,cmd,..` that only grants the listed
+//! ones. `read` stands for all commands that do not change anything, so
+//! `TOKENS="s3cret viewer:read"` allows a read-only client next to a full one.
+
+use std::{collections::HashSet, env, net::SocketAddr, str::FromStr};
+
+use crate::{logln, player::Cmd};
+
+/// Commands and session commands that only look at the player.
+const READ_ONLY: [&str; 12] = [
+    "status",
+    "list_queue",
+    "list_recent",
+    "list_feeds",
+    "get_playable",
+    "list_episodes",
+    "snapshot",
+    "export_opml",
+    "stats",
+    "export_stats",
+    "watch",
+    "unwatch",
+];
+
+/// What a connection is allowed to do.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Access {
+    All,
+    Only(HashSet<String>),
+}
+
+impl Access {
+    /// `name` is the snake case name of a [`Cmd`] or a session command.
+    pub fn allows(&self, name: &str) -> bool {
+        match self {
+            Access::All => true,
+            Access::Only(names) => names.contains(name),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Tokens(Vec<(String, Access)>);
+
+impl Tokens {
+    /// Tokens configured in `TOKENS`, `None` if it is unset or empty.
+    pub fn from_env() -> Option<Self> {
+        let tokens = Tokens::parse(&env::var("TOKENS").ok()?);
+        (!tokens.0.is_empty()).then_some(tokens)
+    }
+
+    pub fn parse(raw: &str) -> Self {
+        let tokens = raw
+            .split_whitespace()
+            .map(|entry| match entry.split_once(':') {
+                None => (entry.to_string(), Access::All),
+                Some((token, names)) => (token.to_string(), Access::Only(allow_list(names))),
+            })
+            .collect();
+        Tokens(tokens)
+    }
+
+    /// The access granted by `token`, if it is one of ours.
+    pub fn check(&self, token: &str) -> Option<Access> {
+        self.0
+            .iter()
+            .find(|(known, _)| constant_time_eq(known.as_bytes(), token.as_bytes()))
+            .map(|(_, access)| access.clone())
+    }
+}
+
+/// Warns when `addr` can be reached from other machines without a token.
+pub fn warn_unprotected(tokens: Option<&Tokens>, addr: SocketAddr) {
+    if tokens.is_none() && !addr.ip().is_loopback() {
+        logln!("warning: no TOKENS set, anyone reaching {addr} has full control");
+    }
+}
+
+fn allow_list(names: &str) -> HashSet<String> {
+    let mut allowed = HashSet::new();
+    for name in names.split(',').filter(|name| !name.is_empty()) {
+        if name == "read" {
+            allowed.extend(READ_ONLY.iter().map(|name| name.to_string()));
+        } else if Cmd::from_str(name).is_ok() || READ_ONLY.contains(&name) {
+            allowed.insert(name.to_string());
+        } else {
+            logln!("TOKENS: ignoring unknown command {name}");
+        }
+    }
+    allowed
+}
+
+/// Compares without bailing out at the first difference, so the time taken
+/// does not tell how much of a guessed token was right.
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse() {
+        let tokens = Tokens::parse(" admin\tviewer:read  dj:queue,next,bogus ");
+        assert_eq!(tokens.check("admin"), Some(Access::All));
+        assert_eq!(tokens.check("admi"), None);
+        assert_eq!(tokens.check("read"), None);
+
+        let viewer = tokens.check("viewer").unwrap();
+        assert!(viewer.allows("status") && viewer.allows("watch"));
+        assert!(viewer.allows("stats") && viewer.allows("export_stats"));
+        assert!(!viewer.allows("queue") && !viewer.allows("shutdown"));
+
+        let dj = tokens.check("dj").unwrap();
+        assert!(dj.allows("queue") && dj.allows("next"));
+        assert!(!dj.allows("bogus") && !dj.allows("status"));
+    }
+}
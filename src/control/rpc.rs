@@ -0,0 +1,228 @@
+//! JSON-RPC 2.0 framing of [`Cmd`]s, one message per line.
+
+use std::str::FromStr;
+
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+
+use crate::player::{Cmd, Response};
+
+pub const PARSE_ERROR: i64 = -32700;
+pub const INVALID_REQUEST: i64 = -32600;
+pub const METHOD_NOT_FOUND: i64 = -32601;
+pub const INVALID_PARAMS: i64 = -32602;
+/// Server defined error: the command was understood but failed to execute.
+pub const COMMAND_FAILED: i64 = -32000;
+/// Server defined error: the connection may not use this command.
+pub const FORBIDDEN: i64 = -32001;
+
+const VERSION: &str = "2.0";
+
+fn version() -> String {
+    VERSION.to_string()
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct RpcRequest {
+    #[serde(default = "version")]
+    pub jsonrpc: String,
+    /// Requests without an id are notifications and get no response.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id: Option<Value>,
+    pub method: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub params: Option<Value>,
+}
+
+impl RpcRequest {
+    pub fn new(cmd: &Cmd, id: Option<Value>) -> Result<Self, serde_json::Error> {
+        let Value::Object(mut fields) = serde_json::to_value(cmd)? else {
+            unreachable!("commands serialize to objects");
+        };
+        Ok(RpcRequest {
+            jsonrpc: version(),
+            id,
+            method: serde_json::from_value(fields.remove("method").unwrap_or_default())?,
+            params: fields.remove("params"),
+        })
+    }
+
+    pub fn to_cmd(&self) -> Result<Cmd, RpcError> {
+        if Cmd::from_str(&self.method).is_err() {
+            return Err(RpcError::new(
+                METHOD_NOT_FOUND,
+                format!("unknown command: {}", self.method),
+            ));
+        }
+
+        let mut fields = Map::new();
+        fields.insert("method".into(), Value::String(self.method.clone()));
+        if let Some(params) = self.params.as_ref().filter(|p| !p.is_null()) {
+            fields.insert("params".into(), params.clone());
+        }
+        serde_json::from_value(Value::Object(fields)).map_err(|err| {
+            RpcError::new(
+                INVALID_PARAMS,
+                format!("invalid params for {}: {err}", self.method),
+            )
+        })
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct RpcResponse {
+    pub jsonrpc: String,
+    pub id: Value,
+    #[serde(flatten)]
+    pub outcome: Outcome,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Outcome {
+    Result(Value),
+    Error(RpcError),
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct RpcError {
+    pub code: i64,
+    pub message: String,
+}
+
+impl RpcError {
+    pub fn new(code: i64, message: impl Into<String>) -> Self {
+        RpcError {
+            code,
+            message: message.into(),
+        }
+    }
+}
+
+impl RpcResponse {
+    pub fn new(id: Option<Value>, outcome: Outcome) -> Self {
+        RpcResponse {
+            jsonrpc: version(),
+            id: id.unwrap_or_default(),
+            outcome,
+        }
+    }
+
+    pub fn from_response(id: Option<Value>, response: Response) -> Self {
+        let outcome = match response {
+            Ok(value) => Outcome::Result(value.unwrap_or_default()),
+            Err(err) => Outcome::Error(RpcError::new(COMMAND_FAILED, err)),
+        };
+        Self::new(id, outcome)
+    }
+
+    pub fn into_response(self) -> Response {
+        match self.outcome {
+            Outcome::Result(Value::Null) => Ok(None),
+            Outcome::Result(value) => Ok(Some(value)),
+            Outcome::Error(err) => Err(err.message),
+        }
+    }
+}
+
+/// Parses one line of JSON into a request, or the error response to send
+/// back when that is not possible. Without `jsonrpc` it is plain JSON, which
+/// is taken as 2.0, any other version is refused.
+pub fn parse_request(line: &str) -> Result<RpcRequest, RpcResponse> {
+    let value: Value = serde_json::from_str(line).map_err(|err| {
+        RpcResponse::new(
+            None,
+            Outcome::Error(RpcError::new(PARSE_ERROR, err.to_string())),
+        )
+    })?;
+    let id = value.get("id").cloned();
+    let invalid = |message: String| {
+        RpcResponse::new(
+            id.clone(),
+            Outcome::Error(RpcError::new(INVALID_REQUEST, message)),
+        )
+    };
+    let request: RpcRequest =
+        serde_json::from_value(value).map_err(|err| invalid(err.to_string()))?;
+    if request.jsonrpc != VERSION {
+        return Err(invalid(format!(
+            "unsupported jsonrpc version: {}",
+            request.jsonrpc
+        )));
+    }
+    Ok(request)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::player::{parse_cmd, UpdateArgs};
+
+    fn cmd(line: &str) -> Result<Cmd, RpcError> {
+        parse_request(line).unwrap().to_cmd()
+    }
+
+    #[test]
+    fn typed_params() {
+        let seek = r#"{"jsonrpc": "2.0", "id": 1, "method": "seek_relative", "params": -30}"#;
+        assert_eq!(cmd(seek), Ok(Cmd::SeekRelative(-30)));
+        assert_eq!(cmd(r#"{"method": "play"}"#), Ok(Cmd::Play));
+        assert_eq!(cmd(r#"{"method": "play", "params": null}"#), Ok(Cmd::Play));
+
+        let update = r#"{"id": "a", "method": "update",
+            "params": {"uri": "https://x/a,b.mp3", "progress": 10, "updated": 5}}"#;
+        let Ok(Cmd::Update(UpdateArgs(uri, playable))) = cmd(update) else {
+            panic!("not an update");
+        };
+        assert_eq!(uri, "https://x/a,b.mp3");
+        assert_eq!(
+            (playable.progress, playable.length, playable.updated),
+            (Some(10), None, Some(5))
+        );
+    }
+
+    #[test]
+    fn errors() {
+        let code = |line| cmd(line).unwrap_err().code;
+        assert_eq!(code(r#"{"method": "dance"}"#), METHOD_NOT_FOUND);
+        assert_eq!(
+            code(r#"{"method": "seek", "params": "soon"}"#),
+            INVALID_PARAMS
+        );
+        let old = parse_request(r#"{"jsonrpc": "1.0", "id": 3, "method": "play"}"#).unwrap_err();
+        assert_eq!(old.id, 3);
+        assert!(matches!(
+            old.outcome,
+            Outcome::Error(RpcError {
+                code: INVALID_REQUEST,
+                ..
+            })
+        ));
+        let not_json = parse_request("{method").unwrap_err();
+        assert!(matches!(
+            not_json.outcome,
+            Outcome::Error(RpcError {
+                code: PARSE_ERROR,
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn round_trip() {
+        let cmd = Cmd::Update(UpdateArgs::parse("file:///a,b.mp3,1,2,3").unwrap());
+        let request = RpcRequest::new(&cmd, Some(7.into())).unwrap();
+        let line = serde_json::to_string(&request).unwrap();
+        assert_eq!(parse_request(&line).unwrap().to_cmd(), Ok(cmd));
+        assert_eq!(
+            parse_cmd("update(file:///a,b.mp3,1,2,3)").map(|_| ()),
+            Some(())
+        );
+
+        let response = RpcResponse::from_response(Some(7.into()), Ok(None));
+        let line = serde_json::to_string(&response).unwrap();
+        assert_eq!(line, r#"{"jsonrpc":"2.0","id":7,"result":null}"#);
+        let back: RpcResponse = serde_json::from_str(&line).unwrap();
+        assert_eq!(back.into_response(), Ok(None));
+    }
+}
@@ -0,0 +1,180 @@
+//! Where podaemon keeps its files: `$PODAEMON_DATA_DIR` if set, otherwise
+//! `$XDG_DATA_HOME/podaemon` or `~/.local/share/podaemon`. What can be fetched
+//! again goes to `$PODAEMON_CACHE_DIR`, `$XDG_CACHE_HOME/podaemon` or
+//! `~/.cache/podaemon`.
+//!
+//! Files are replaced atomically: the new content goes to a temporary file
+//! that is synced and then renamed over the old one, so a crash leaves
+//! either the old or the new version. The library database is backed up
+//! whenever podaemon starts, the previous versions are kept as `<name>.1`
+//! (newest) to `<name>.<STATE_BACKUPS>`, 3 by default.
+
+use std::{
+    env,
+    fs::{self, File},
+    io::{self, Write},
+    path::{Path, PathBuf},
+};
+
+use chrono::Local;
+
+use crate::logln;
+
+const DEFAULT_BACKUPS: usize = 3;
+
+pub fn data_dir() -> PathBuf {
+    xdg_dir("PODAEMON_DATA_DIR", "XDG_DATA_HOME", ".local/share")
+}
+
+pub fn cache_dir() -> PathBuf {
+    xdg_dir("PODAEMON_CACHE_DIR", "XDG_CACHE_HOME", ".cache")
+}
+
+fn xdg_dir(own: &str, xdg: &str, default: &str) -> PathBuf {
+    if let Ok(dir) = env::var(own) {
+        return PathBuf::from(dir);
+    }
+    match env::var(xdg) {
+        Ok(dir) if !dir.is_empty() => PathBuf::from(dir).join("podaemon"),
+        _ => match env::var("HOME") {
+            Ok(home) => PathBuf::from(home).join(default).join("podaemon"),
+            Err(_) => PathBuf::from("."),
+        },
+    }
+}
+
+/// `name` in the data dir.
+pub fn path(name: &str) -> PathBuf {
+    data_dir().join(name)
+}
+
+/// Like [`path`], but if only `./name` exists, where earlier versions kept
+/// their files, it is copied there first. Only if `valid` accepts its content,
+/// the working directory may hold an unrelated file of that name. The
+/// original is left in place.
+pub fn legacy_path(name: &str, valid: impl Fn(&[u8]) -> bool) -> PathBuf {
+    let path = path(name);
+    if let Err(err) = migrate(Path::new(name), &path, valid) {
+        logln!("failed to copy {name} to {}: {err}", path.display());
+        return PathBuf::from(name);
+    }
+    path
+}
+
+fn migrate(old: &Path, new: &Path, valid: impl Fn(&[u8]) -> bool) -> io::Result<()> {
+    if new.exists() || !old.is_file() {
+        return Ok(());
+    }
+    let content = fs::read(old)?;
+    if !valid(&content) {
+        logln!("ignoring {}, it is not a podaemon file", old.display());
+        return Ok(());
+    }
+    write_atomic(new, &content)?;
+    logln!(
+        "copied {} to {}, the original can be removed",
+        old.display(),
+        new.display()
+    );
+    Ok(())
+}
+
+pub fn backups() -> usize {
+    match env::var("STATE_BACKUPS") {
+        Ok(n) => n.parse().unwrap_or_else(|_| {
+            logln!("invalid STATE_BACKUPS: {n}");
+            DEFAULT_BACKUPS
+        }),
+        Err(_) => DEFAULT_BACKUPS,
+    }
+}
+
+pub fn backup_path(path: &Path, n: usize) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(format!(".{n}"));
+    PathBuf::from(name)
+}
+
+/// Moves the backups of `path` one up, dropping the oldest of `backups`, so
+/// that `<name>.1` can take the newest.
+pub fn rotate_backups(path: &Path, backups: usize) -> io::Result<()> {
+    for n in (1..backups).rev() {
+        let from = backup_path(path, n);
+        if from.exists() {
+            fs::rename(&from, backup_path(path, n + 1))?;
+        }
+    }
+    Ok(())
+}
+
+/// Where a file that cannot be read is moved aside, `<name>.corrupt-<time>`
+/// numbered if that is taken.
+pub fn quarantine_path(path: &Path) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(format!(".corrupt-{}", Local::now().format("%Y%m%dT%H%M%S")));
+    let mut quarantine = PathBuf::from(&name);
+    for n in 1.. {
+        if !quarantine.exists() {
+            break;
+        }
+        let mut numbered = name.clone();
+        numbered.push(format!("-{n}"));
+        quarantine = PathBuf::from(numbered);
+    }
+    quarantine
+}
+
+/// Replaces `path` with `contents`.
+pub fn write_atomic(path: &Path, contents: &[u8]) -> io::Result<()> {
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    fs::create_dir_all(dir)?;
+
+    let mut tmp_name = path.as_os_str().to_owned();
+    tmp_name.push(".tmp");
+    let tmp = PathBuf::from(tmp_name);
+    let mut file = File::create(&tmp)?;
+    file.write_all(contents)?;
+    file.sync_all()?;
+    drop(file);
+
+    fs::rename(&tmp, path)?;
+    // make the rename itself durable
+    File::open(dir)?.sync_all()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rotates_backups() {
+        let dir = env::temp_dir().join(format!("podaemon-data-{}", std::process::id()));
+        let path = dir.join("state");
+        for version in 1..=4 {
+            if path.exists() {
+                rotate_backups(&path, 2).unwrap();
+                fs::copy(&path, backup_path(&path, 1)).unwrap();
+            }
+            write_atomic(&path, format!("{version}").as_bytes()).unwrap();
+        }
+        let read = |path: PathBuf| fs::read_to_string(path).unwrap();
+        assert_eq!(read(path.clone()), "4");
+        assert_eq!(read(backup_path(&path, 1)), "3");
+        assert_eq!(read(backup_path(&path, 2)), "2");
+        assert!(!backup_path(&path, 3).exists());
+        assert!(!dir.join("state.tmp").exists());
+
+        let old = dir.join("old");
+        fs::write(&old, "legacy").unwrap();
+        let new = dir.join("sub").join("new");
+        migrate(&old, &new, |content| content == b"other").unwrap();
+        assert!(!new.exists());
+        migrate(&old, &new, |content| content == b"legacy").unwrap();
+        assert_eq!(read(new), "legacy");
+        assert_eq!(read(old), "legacy");
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
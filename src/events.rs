@@ -0,0 +1,67 @@
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+use tokio::sync::broadcast;
+
+/// Something that happened in the player, pushed to every control connection
+/// that asked to `watch`. Serialized as one JSON object per line, tagged with
+/// `event`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum Event {
+    TrackChanged {
+        uri: String,
+        title: Option<String>,
+        album: Option<String>,
+    },
+    PlayState {
+        state: String,
+        playing: bool,
+    },
+    /// Sent every second while playing, times are in seconds.
+    Position {
+        uri: String,
+        position: u64,
+        duration: Option<u64>,
+    },
+    Finished {
+        uri: String,
+    },
+    QueueChanged {
+        len: usize,
+    },
+    /// A request for the feed started or ended.
+    FeedRefreshing {
+        uri: String,
+        refreshing: bool,
+    },
+    /// `new_episodes` counts the episodes that were not in the feed before.
+    FeedRefreshed {
+        uri: String,
+        title: String,
+        new_episodes: usize,
+    },
+    /// Sent before [`Event::FeedRefreshed`] if a refresh brought new episodes,
+    /// with their titles.
+    NewEpisodes {
+        uri: String,
+        title: String,
+        episodes: Vec<String>,
+    },
+    Unsubscribed {
+        uri: String,
+    },
+    Error {
+        message: String,
+    },
+}
+
+static EVENTS: Lazy<broadcast::Sender<Event>> = Lazy::new(|| broadcast::channel(64).0);
+
+pub fn emit(event: Event) {
+    // no one watching is not an error
+    let _ = EVENTS.send(event);
+}
+
+pub fn subscribe() -> broadcast::Receiver<Event> {
+    EVENTS.subscribe()
+}
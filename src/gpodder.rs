@@ -0,0 +1,645 @@
+//! Sync of subscriptions and episode progress with a gpodder.net compatible
+//! server, e.g. to share progress with AntennaPod. Enabled by `GPODDER_URL`
+//! together with `GPODDER_USER` and `GPODDER_PASS`, `GPODDER_DEVICE` names
+//! this device (`podaemon` by default). A url ending in `gpoddersync`, like
+//! `https://cloud.example.com/index.php/apps/gpoddersync`, is taken to be the
+//! Nextcloud app, anything else is spoken to with the gpodder.net v2 API.
+//!
+//! Like the other clients of the player this only goes through [`Cmd`]s.
+//! `play` actions carry progress, their `started` is where the last listening
+//! session began. Conflicting progress is resolved by [`Cmd::Update`], the
+//! newer timestamp wins. podaemon streams instead of downloading, so the
+//! queue stands in for the downloads: a `download` queues the episode and a
+//! `delete` takes it off the queue, and queueing an episode or removing it
+//! unplayed is pushed as such. Local queue changes win over remote ones to the
+//! same episode. `new` actions are skipped.
+
+use std::{
+    collections::{BTreeSet, HashMap},
+    env,
+    error::Error,
+    fs::File,
+    io::BufReader,
+    time::Duration,
+};
+
+use chrono::{DateTime, NaiveDateTime};
+use reqwest::RequestBuilder;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use tokio::{sync::mpsc::Sender, time};
+
+use crate::{
+    control::execute,
+    data, logln,
+    player::{
+        query::Snapshot,
+        state::{get_time, Episode, Playable},
+        stats::{ExportFormat, Session},
+        Cmd, Request, UpdateArgs,
+    },
+};
+
+const FILE: &str = "gpodder";
+const DEFAULT_INTERVAL: u64 = 15 * 60;
+
+type Result<T> = std::result::Result<T, Box<dyn Error + Send + Sync>>;
+
+#[derive(Debug, Clone)]
+pub struct Config {
+    pub url: String,
+    pub user: String,
+    pub pass: String,
+    pub device: String,
+}
+
+impl Config {
+    pub fn from_env() -> Option<Self> {
+        Some(Config {
+            url: env::var("GPODDER_URL")
+                .ok()?
+                .trim_end_matches('/')
+                .to_string(),
+            user: env::var("GPODDER_USER").ok()?,
+            pass: env::var("GPODDER_PASS").ok()?,
+            device: env::var("GPODDER_DEVICE").unwrap_or_else(|_| "podaemon".into()),
+        })
+    }
+
+    fn is_nextcloud(&self) -> bool {
+        self.url.ends_with("gpoddersync")
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Default, PartialEq)]
+pub struct SubscriptionChanges {
+    pub add: Vec<String>,
+    pub remove: Vec<String>,
+    #[serde(default, skip_serializing)]
+    pub timestamp: u64,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct EpisodeAction {
+    pub podcast: String,
+    pub episode: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub device: Option<String>,
+    pub action: String,
+    /// UTC, as `2009-12-12T09:00:00`.
+    pub timestamp: String,
+    // Nextcloud reports missing values as -1
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub started: Option<i64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub position: Option<i64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub total: Option<i64>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct EpisodeActions {
+    pub actions: Vec<EpisodeAction>,
+    pub timestamp: u64,
+}
+
+pub struct Client {
+    http: reqwest::Client,
+    config: Config,
+}
+
+impl Client {
+    pub fn new(config: Config) -> Self {
+        Client {
+            http: reqwest::Client::new(),
+            config,
+        }
+    }
+
+    fn subscriptions_url(&self) -> String {
+        let Config {
+            url, user, device, ..
+        } = &self.config;
+        if self.config.is_nextcloud() {
+            format!("{url}/subscriptions")
+        } else {
+            format!("{url}/api/2/subscriptions/{user}/{device}.json")
+        }
+    }
+
+    fn subscription_upload_url(&self) -> String {
+        if self.config.is_nextcloud() {
+            format!("{}/subscription_change/create", self.config.url)
+        } else {
+            self.subscriptions_url()
+        }
+    }
+
+    fn actions_url(&self) -> String {
+        let Config { url, user, .. } = &self.config;
+        if self.config.is_nextcloud() {
+            format!("{url}/episode_action")
+        } else {
+            format!("{url}/api/2/episodes/{user}.json")
+        }
+    }
+
+    fn actions_upload_url(&self) -> String {
+        if self.config.is_nextcloud() {
+            format!("{}/episode_action/create", self.config.url)
+        } else {
+            self.actions_url()
+        }
+    }
+
+    async fn send<T: DeserializeOwned>(&self, request: RequestBuilder) -> Result<T> {
+        let response = request
+            .basic_auth(&self.config.user, Some(&self.config.pass))
+            .send()
+            .await?
+            .error_for_status()?;
+        Ok(response.json().await?)
+    }
+
+    pub async fn pull_subscriptions(&self, since: u64) -> Result<SubscriptionChanges> {
+        let request = self
+            .http
+            .get(self.subscriptions_url())
+            .query(&[("since", since)]);
+        self.send(request).await
+    }
+
+    pub async fn push_subscriptions(&self, changes: &SubscriptionChanges) -> Result<()> {
+        let request = self.http.post(self.subscription_upload_url()).json(changes);
+        self.send::<serde_json::Value>(request).await.map(|_| ())
+    }
+
+    pub async fn pull_actions(&self, since: u64) -> Result<EpisodeActions> {
+        let request = self.http.get(self.actions_url()).query(&[("since", since)]);
+        self.send(request).await
+    }
+
+    pub async fn push_actions(&self, actions: &[EpisodeAction]) -> Result<()> {
+        let request = self.http.post(self.actions_upload_url()).json(actions);
+        self.send::<serde_json::Value>(request).await.map(|_| ())
+    }
+}
+
+/// What we know about the server, kept between runs.
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct SyncState {
+    subscriptions_since: u64,
+    actions_since: u64,
+    /// Subscriptions as of the last sync, to tell what changed locally since.
+    synced_feeds: BTreeSet<String>,
+    /// Newest progress timestamp per episode that the server has.
+    known: HashMap<String, u64>,
+    /// The queue as of the last sync.
+    #[serde(default)]
+    synced_queue: BTreeSet<String>,
+}
+
+impl SyncState {
+    fn from_disc() -> Self {
+        let valid = |content: &[u8]| serde_json::from_slice::<SyncState>(content).is_ok();
+        let Ok(file) = File::open(data::legacy_path(FILE, valid)) else {
+            return SyncState::default();
+        };
+        serde_json::from_reader(BufReader::new(file)).unwrap_or_else(|err| {
+            logln!("ignoring unreadable {FILE}: {err}");
+            SyncState::default()
+        })
+    }
+
+    fn to_disc(&self) -> Result<()> {
+        data::write_atomic(&data::path(FILE), &serde_json::to_vec(self)?)?;
+        Ok(())
+    }
+}
+
+/// Syncs right away and then every `GPODDER_INTERVAL` seconds.
+pub async fn run(config: Config, queue: Sender<Request>) {
+    let secs = env::var("GPODDER_INTERVAL")
+        .ok()
+        .and_then(|secs| secs.parse().ok())
+        .unwrap_or(DEFAULT_INTERVAL);
+    logln!("syncing with {} every {secs}s", config.url);
+
+    let client = Client::new(config);
+    let mut state = SyncState::from_disc();
+    let mut interval = time::interval(Duration::from_secs(secs.max(1)));
+    loop {
+        interval.tick().await;
+        if queue.is_closed() {
+            return;
+        }
+        match sync(&client, &mut state, &queue).await {
+            Ok(()) => {
+                if let Err(err) = state.to_disc() {
+                    logln!("failed to save {FILE}: {err}");
+                }
+            }
+            Err(err) => logln!("gpodder sync failed: {err}"),
+        }
+    }
+}
+
+async fn query<T: DeserializeOwned>(queue: &Sender<Request>, cmd: Cmd) -> Result<T> {
+    let value = execute(cmd, queue).await?.unwrap_or_default();
+    Ok(serde_json::from_value(value)?)
+}
+
+pub async fn sync(client: &Client, state: &mut SyncState, queue: &Sender<Request>) -> Result<()> {
+    let snapshot: Snapshot = query(queue, Cmd::Snapshot).await?;
+
+    // subscriptions, local changes win over remote ones to the same feed
+    let mut feeds: BTreeSet<String> = snapshot.feeds.iter().map(|f| f.uri.clone()).collect();
+    let local = SubscriptionChanges {
+        add: feeds.difference(&state.synced_feeds).cloned().collect(),
+        remove: state.synced_feeds.difference(&feeds).cloned().collect(),
+        timestamp: 0,
+    };
+    let remote = client.pull_subscriptions(state.subscriptions_since).await?;
+    for url in remote.add {
+        if !local.remove.contains(&url) && feeds.insert(url.clone()) {
+            execute(Cmd::Subscribe(url), queue).await?;
+        }
+    }
+    for url in remote.remove {
+        if !local.add.contains(&url) && feeds.remove(&url) {
+            execute(Cmd::Unsubscribe(url), queue).await?;
+        }
+    }
+    if !local.add.is_empty() || !local.remove.is_empty() {
+        client.push_subscriptions(&local).await?;
+    }
+    // our own changes come back with the next pull, they are no-ops by then
+    state.subscriptions_since = remote.timestamp;
+    state.synced_feeds = feeds;
+
+    // progress and queue
+    let mut queued: Vec<String> = snapshot.queue.iter().cloned().collect();
+    let now: BTreeSet<String> = queued.iter().cloned().collect();
+    let added: BTreeSet<String> = now.difference(&state.synced_queue).cloned().collect();
+    let removed: BTreeSet<String> = state.synced_queue.difference(&now).cloned().collect();
+    let remote = client.pull_actions(state.actions_since).await?;
+    for action in remote.actions {
+        // our own queue changes come back as well
+        let own = action.device.as_deref() == Some(client.config.device.as_str());
+        let episode = &action.episode;
+        match action.action.to_ascii_lowercase().as_str() {
+            "download" if !own && !removed.contains(episode) && !queued.contains(episode) => {
+                execute(Cmd::Queue(episode.clone()), queue).await?;
+                queued.push(episode.clone());
+            }
+            "delete" if !own && !added.contains(episode) && queued.contains(episode) => {
+                // it may have left the queue since the snapshot
+                if let Err(err) = execute(Cmd::Dequeue(episode.clone()), queue).await {
+                    logln!("gpodder: {err}");
+                }
+                queued.retain(|uri| uri != episode);
+            }
+            _ => {}
+        }
+        let Some(update) = to_update(&action) else {
+            continue;
+        };
+        let known = state.known.entry(update.0.clone()).or_default();
+        *known = update.1.updated.unwrap_or_default().max(*known);
+        execute(Cmd::Update(update), queue).await?;
+    }
+    state.actions_since = remote.timestamp;
+
+    let episodes: Vec<Episode> = query(queue, Cmd::ListEpisodes).await?;
+    let export: Export = query(queue, Cmd::ExportStats(ExportFormat::Json)).await?;
+    let podcasts = podcasts(&snapshot, &episodes);
+    let device = &client.config.device;
+    let mut actions = local_actions(&snapshot, &podcasts, &export.sessions, state, device);
+    let queue_actions = queue_actions(&snapshot, &podcasts, &added, &removed, device);
+    actions.extend(queue_actions);
+    if !actions.is_empty() {
+        logln!("pushing {} episode actions", actions.len());
+        client.push_actions(&actions).await?;
+        for (uri, playable) in &snapshot.uris {
+            if actions
+                .iter()
+                .any(|a| &a.episode == uri && a.action == "play")
+            {
+                state
+                    .known
+                    .insert(uri.clone(), playable.updated.unwrap_or_default());
+            }
+        }
+    }
+    state.synced_queue = queued.into_iter().collect();
+    Ok(())
+}
+
+/// The part of [`Cmd::ExportStats`] needed here.
+#[derive(Deserialize)]
+struct Export {
+    sessions: Vec<Session>,
+}
+
+/// The feed of every episode, as far as it is known. The server wants it
+/// with every action.
+fn podcasts<'a>(snapshot: &'a Snapshot, episodes: &'a [Episode]) -> HashMap<&'a str, &'a str> {
+    let feed_of_title: HashMap<&str, &str> = snapshot
+        .feeds
+        .iter()
+        .filter_map(|feed| Some((feed.title.as_deref()?, feed.uri.as_str())))
+        .collect();
+    let mut podcasts: HashMap<&str, &str> = snapshot
+        .uris
+        .iter()
+        .filter_map(|(uri, playable)| Some((uri.as_str(), playable.source.as_deref()?)))
+        .collect();
+    podcasts.extend(episodes.iter().filter_map(|episode| {
+        let url = episode.item.enclosure()?.url();
+        Some((url, *feed_of_title.get(episode.channel_title.as_str())?))
+    }));
+    podcasts
+}
+
+/// `download` for episodes queued since the last sync, `delete` for those
+/// taken off the queue without being listened to.
+fn queue_actions(
+    snapshot: &Snapshot,
+    podcasts: &HashMap<&str, &str>,
+    added: &BTreeSet<String>,
+    removed: &BTreeSet<String>,
+    device: &str,
+) -> Vec<EpisodeAction> {
+    let Some(timestamp) = format_timestamp(get_time()) else {
+        return Vec::new();
+    };
+    let unplayed = |uri: &String| {
+        snapshot.status.uri.as_ref() != Some(uri)
+            && !snapshot
+                .uris
+                .get(uri)
+                .is_some_and(|p| p.progress.is_some() || p.finished.is_some())
+    };
+    let added = added.iter().map(|uri| (uri, "download"));
+    let removed = removed.iter().filter(|uri| unplayed(uri));
+    added
+        .chain(removed.map(|uri| (uri, "delete")))
+        .filter_map(|(uri, action)| {
+            Some(EpisodeAction {
+                podcast: podcasts.get(uri.as_str())?.to_string(),
+                episode: uri.clone(),
+                device: Some(device.to_string()),
+                action: action.into(),
+                timestamp: timestamp.clone(),
+                started: None,
+                position: None,
+                total: None,
+            })
+        })
+        .collect()
+}
+
+fn to_update(action: &EpisodeAction) -> Option<UpdateArgs> {
+    if !action.action.eq_ignore_ascii_case("play") {
+        return None;
+    }
+    let seconds = |value: Option<i64>| value.filter(|v| *v >= 0).map(|v| v as u64);
+    Some(UpdateArgs(
+        action.episode.clone(),
+        Playable {
+            progress: Some(seconds(action.position)?),
+            length: seconds(action.total).filter(|total| *total > 0),
+            updated: Some(parse_timestamp(&action.timestamp)?),
+            ..Playable::default()
+        },
+    ))
+}
+
+/// `play` actions for progress the server does not know about yet, started
+/// where the last listening `sessions` of the episode began. Only episodes of
+/// known feeds qualify.
+fn local_actions(
+    snapshot: &Snapshot,
+    podcasts: &HashMap<&str, &str>,
+    sessions: &[Session],
+    state: &SyncState,
+    device: &str,
+) -> Vec<EpisodeAction> {
+    // oldest first, the last one wins
+    let started: HashMap<&str, u64> = sessions
+        .iter()
+        .filter_map(|session| Some((session.uri.as_str(), session.from?)))
+        .collect();
+
+    snapshot
+        .uris
+        .iter()
+        .filter_map(|(uri, playable)| {
+            let updated = playable.updated?;
+            if updated <= state.known.get(uri).copied().unwrap_or_default() {
+                return None;
+            }
+            Some(EpisodeAction {
+                podcast: podcasts.get(uri.as_str())?.to_string(),
+                episode: uri.clone(),
+                device: Some(device.to_string()),
+                action: "play".into(),
+                timestamp: format_timestamp(updated)?,
+                started: Some(started.get(uri.as_str()).copied().unwrap_or_default() as i64),
+                // played to the end is how other clients mark an episode played
+                position: Some(
+                    playable
+                        .progress
+                        .or(playable.finished.and(playable.length))? as i64,
+                ),
+                total: playable.length.map(|total| total as i64),
+            })
+        })
+        .collect()
+}
+
+fn format_timestamp(secs: u64) -> Option<String> {
+    let time = DateTime::from_timestamp(secs as i64, 0)?;
+    Some(time.format("%Y-%m-%dT%H:%M:%S").to_string())
+}
+
+fn parse_timestamp(raw: &str) -> Option<u64> {
+    let time =
+        match NaiveDateTime::parse_from_str(raw.trim_end_matches('Z'), "%Y-%m-%dT%H:%M:%S%.f") {
+            Ok(time) => time.and_utc().timestamp(),
+            Err(_) => DateTime::parse_from_rfc3339(raw).ok()?.timestamp(),
+        };
+    u64::try_from(time).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use axum::{extract::State, routing::get, Json, Router, Server};
+    use rss::{Enclosure, Item};
+    use serde_json::{json, Value};
+    use tokio::sync::mpsc;
+
+    use super::*;
+    use crate::player::query::FeedInfo;
+
+    type Posted = Arc<Mutex<Vec<Value>>>;
+
+    async fn record(State(posted): State<Posted>, Json(body): Json<Value>) -> Json<Value> {
+        posted.lock().unwrap().push(body);
+        Json(json!({ "timestamp": 30, "update_urls": [] }))
+    }
+
+    /// gpodder.net with one new subscription and actions of other devices and
+    /// of this one.
+    async fn mock_server() -> (String, Posted) {
+        let subscriptions = || async {
+            Json(json!({ "add": ["http://c/feed"], "remove": ["http://a/feed"], "timestamp": 10 }))
+        };
+        let actions = || async {
+            Json(json!({
+                "actions": [
+                    { "podcast": "http://b/feed", "episode": "http://b/1.mp3", "action": "PLAY",
+                      "timestamp": "2024-01-01T00:00:00", "started": 0, "position": 60, "total": 600 },
+                    { "podcast": "http://b/feed", "episode": "http://b/2.mp3", "action": "download",
+                      "timestamp": "2024-01-01T00:00:00" },
+                    { "podcast": "http://b/feed", "episode": "http://b/3.mp3", "action": "delete",
+                      "timestamp": "2024-01-01T00:00:00", "device": "phone" },
+                    { "podcast": "http://b/feed", "episode": "http://b/4.mp3", "action": "download",
+                      "timestamp": "2024-01-01T00:00:00", "device": "test" },
+                ],
+                "timestamp": 20,
+            }))
+        };
+        let posted = Posted::default();
+        let app = Router::new()
+            .route(
+                "/api/2/subscriptions/me/test.json",
+                get(subscriptions).post(record),
+            )
+            .route("/api/2/episodes/me.json", get(actions).post(record))
+            .with_state(posted.clone());
+        let server = Server::bind(&"127.0.0.1:0".parse().unwrap()).serve(app.into_make_service());
+        let url = format!("http://{}", server.local_addr());
+        tokio::spawn(server);
+        (url, posted)
+    }
+
+    /// A player subscribed to feed `a` that has progress on one of its episodes
+    /// and queued another one after `b/3`.
+    fn fake_player() -> (Sender<Request>, Arc<Mutex<Vec<Cmd>>>) {
+        let mut snapshot = Snapshot::default();
+        snapshot.feeds.push(FeedInfo {
+            uri: "http://a/feed".into(),
+            title: Some("A".into()),
+            episodes: 1,
+            refreshing: false,
+            new_episodes: 0,
+        });
+        let playable = Playable {
+            progress: Some(30),
+            updated: Some(1_704_067_300),
+            ..Playable::default()
+        };
+        snapshot.uris.insert("http://a/1.mp3".into(), playable);
+        let queued = Playable {
+            source: Some("http://a/feed".into()),
+            ..Playable::default()
+        };
+        snapshot.uris.insert("http://a/2.mp3".into(), queued);
+        snapshot.queue = ["http://b/3.mp3".into(), "http://a/2.mp3".into()].into();
+        let export = json!({ "sessions": [
+            { "uri": "http://a/1.mp3", "feed": "A", "started": 1, "stopped": 2, "from": 0, "to": 5 },
+            { "uri": "http://a/1.mp3", "feed": "A", "started": 3, "stopped": 4, "from": 12, "to": 30 },
+        ]});
+
+        let mut enclosure = Enclosure::default();
+        enclosure.set_url("http://a/1.mp3");
+        let mut item = Item::default();
+        item.set_enclosure(enclosure);
+        let episodes = vec![Episode {
+            channel_title: "A".into(),
+            item,
+        }];
+
+        let (tx, mut rx) = mpsc::channel::<Request>(8);
+        let received = Arc::new(Mutex::new(Vec::new()));
+        let log = received.clone();
+        tokio::spawn(async move {
+            while let Some(Request { cmd, reply }) = rx.recv().await {
+                let response = match cmd {
+                    Cmd::Snapshot => serde_json::to_value(&snapshot).ok(),
+                    Cmd::ListEpisodes => serde_json::to_value(&episodes).ok(),
+                    Cmd::ExportStats(_) => Some(export.clone()),
+                    _ => None,
+                };
+                log.lock().unwrap().push(cmd);
+                reply.send(Ok(response));
+            }
+        });
+        (tx, received)
+    }
+
+    #[tokio::test]
+    async fn sync_with_server() {
+        let (url, posted) = mock_server().await;
+        let (queue, received) = fake_player();
+        let client = Client::new(Config {
+            url,
+            user: "me".into(),
+            pass: "secret".into(),
+            device: "test".into(),
+        });
+        let mut state = SyncState {
+            synced_feeds: BTreeSet::from(["http://old/feed".into()]),
+            synced_queue: BTreeSet::from(["http://b/3.mp3".into()]),
+            ..SyncState::default()
+        };
+
+        sync(&client, &mut state, &queue).await.unwrap();
+
+        // the remote removal of `a` loses against it being subscribed locally
+        let update = UpdateArgs(
+            "http://b/1.mp3".into(),
+            Playable {
+                progress: Some(60),
+                length: Some(600),
+                updated: Some(1_704_067_200),
+                ..Playable::default()
+            },
+        );
+        // the download of this device is its own queueing coming back
+        assert_eq!(
+            received.lock().unwrap()[1..5],
+            [
+                Cmd::Subscribe("http://c/feed".into()),
+                Cmd::Update(update),
+                Cmd::Queue("http://b/2.mp3".into()),
+                Cmd::Dequeue("http://b/3.mp3".into()),
+            ]
+        );
+        let posted = posted.lock().unwrap();
+        assert_eq!(
+            posted[0],
+            json!({ "add": ["http://a/feed"], "remove": ["http://old/feed"] })
+        );
+        let actions = posted[1].as_array().unwrap();
+        assert_eq!(
+            actions[0],
+            json!({
+                "podcast": "http://a/feed", "episode": "http://a/1.mp3", "device": "test",
+                "action": "play", "timestamp": "2024-01-01T00:01:40", "started": 12, "position": 30,
+            })
+        );
+        assert_eq!(actions[1]["episode"], "http://a/2.mp3");
+        assert_eq!(actions[1]["action"], "download");
+        assert_eq!(actions.len(), 2);
+        assert_eq!((state.subscriptions_since, state.actions_since), (10, 20));
+        assert_eq!(state.known["http://a/1.mp3"], 1_704_067_300);
+        assert!(state.synced_feeds.contains("http://c/feed"));
+        assert_eq!(
+            state.synced_queue,
+            BTreeSet::from(["http://a/2.mp3".into(), "http://b/2.mp3".into()])
+        );
+    }
+}
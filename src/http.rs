@@ -0,0 +1,290 @@
+//! REST interface and a small web remote, served when `HTTP_PORT` is set.
+//!
+//! Queries are plain `GET`s under `/api`, every [`Cmd`] can be sent as
+//! `POST /api/cmd/<method>` with its params, if any, as the JSON body:
+//! `curl -H 'Content-Type: application/json' -d 30 localhost:8080/api/cmd/seek_relative`.
+//! Commands without that content type or from another `Origin` are refused,
+//! so web pages cannot send them through the browser. With `TOKENS`
+//! configured requests need an `Authorization: Bearer <token>` header.
+
+use std::{collections::HashMap, net::SocketAddr, sync::Arc};
+
+use axum::{
+    body::Bytes,
+    extract::{ConnectInfo, Path, Query, State},
+    http::{header, HeaderMap, StatusCode},
+    response::{Html, IntoResponse, Response},
+    routing::{get, post},
+    Json, Router, Server,
+};
+use serde_json::{json, Value};
+use tokio::sync::mpsc::Sender;
+
+use crate::{
+    control::{
+        self,
+        auth::{self, Access, Tokens},
+        rpc::{self, RpcRequest},
+    },
+    logln,
+    player::{Cmd, Request},
+};
+
+const INDEX: &str = include_str!("../static/index.html");
+
+#[derive(Clone)]
+struct App {
+    queue: Sender<Request>,
+    tokens: Option<Arc<Tokens>>,
+}
+
+pub async fn serve(
+    queue: Sender<Request>,
+    bind_addr: &str,
+    port: &str,
+    tokens: Option<Arc<Tokens>>,
+) {
+    let Ok(port) = port.parse::<u16>() else {
+        logln!("invalid HTTP_PORT: {port}");
+        return;
+    };
+    let server = match std::net::TcpListener::bind((bind_addr, port))
+        .map_err(|err| err.to_string())
+        .and_then(|listener| Server::from_tcp(listener).map_err(|err| err.to_string()))
+    {
+        Ok(server) => server,
+        Err(err) => {
+            logln!("failed to bind http {bind_addr}:{port}: {err}");
+            return;
+        }
+    };
+
+    let app = Router::new()
+        .route("/", get(|| async { Html(INDEX) }))
+        .route("/api/status", get(status))
+        .route("/api/queue", get(list_queue))
+        .route("/api/recent", get(list_recent))
+        .route("/api/feeds", get(list_feeds))
+        .route("/api/episodes", get(list_episodes))
+        .route("/api/playable", get(get_playable))
+        .route("/api/cmd/:method", post(command))
+        .with_state(App {
+            queue,
+            tokens: tokens.clone(),
+        });
+
+    let server = server.serve(app.into_make_service_with_connect_info::<SocketAddr>());
+    logln!("http listening on: {}", server.local_addr());
+    auth::warn_unprotected(tokens.as_deref(), server.local_addr());
+    if let Err(err) = server.await {
+        logln!("http server failed: {err}");
+    }
+}
+
+impl App {
+    /// Executes `cmd` if the request is allowed to, see [`App::execute`].
+    async fn run(&self, peer: SocketAddr, headers: &HeaderMap, cmd: Cmd) -> Response {
+        match self.authorize(peer, headers, &cmd.to_string()) {
+            Ok(()) => self.execute(cmd).await,
+            Err((status, message)) => error(status, message),
+        }
+    }
+
+    fn authorize(
+        &self,
+        peer: SocketAddr,
+        headers: &HeaderMap,
+        name: &str,
+    ) -> Result<(), (StatusCode, String)> {
+        let Some(access) = self.access(headers) else {
+            logln!("rejected http request from {peer}: invalid token");
+            return Err((StatusCode::UNAUTHORIZED, "invalid token".into()));
+        };
+        if !access.allows(name) {
+            logln!("refused {name} from {peer}");
+            return Err((StatusCode::FORBIDDEN, format!("not permitted: {name}")));
+        }
+        Ok(())
+    }
+
+    /// The JSON result of a query, `204` for commands that went through and
+    /// `{"error": ..}` otherwise.
+    async fn execute(&self, cmd: Cmd) -> Response {
+        match control::execute(cmd, &self.queue).await {
+            Ok(Some(value)) => Json(value).into_response(),
+            Ok(None) => StatusCode::NO_CONTENT.into_response(),
+            Err(err) => error(StatusCode::CONFLICT, err),
+        }
+    }
+
+    fn access(&self, headers: &HeaderMap) -> Option<Access> {
+        let Some(tokens) = &self.tokens else {
+            return Some(Access::All);
+        };
+        let token = headers
+            .get(header::AUTHORIZATION)?
+            .to_str()
+            .ok()?
+            .strip_prefix("Bearer ")?;
+        tokens.check(token.trim())
+    }
+}
+
+/// Browsers send form posts and the like to any site without asking, but not
+/// JSON, and they tell where a request comes from in `Origin`.
+fn same_site_json(headers: &HeaderMap) -> Result<(), (StatusCode, String)> {
+    let header = |name| headers.get(name).and_then(|value| value.to_str().ok());
+    let content_type = header(header::CONTENT_TYPE).unwrap_or_default();
+    let media_type = content_type.split(';').next().unwrap_or_default().trim();
+    if !media_type.eq_ignore_ascii_case("application/json") {
+        return Err((
+            StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            "content type must be application/json".into(),
+        ));
+    }
+    if let Some(origin) = headers.get(header::ORIGIN) {
+        let origin_host = origin
+            .to_str()
+            .ok()
+            .and_then(|origin| origin.split_once("://"))
+            .map(|(_, host)| host);
+        if origin_host.is_none() || origin_host != header(header::HOST) {
+            return Err((StatusCode::FORBIDDEN, "cross-origin request".into()));
+        }
+    }
+    Ok(())
+}
+
+fn error(status: StatusCode, message: String) -> Response {
+    (status, Json(json!({ "error": message }))).into_response()
+}
+
+type Peer = ConnectInfo<SocketAddr>;
+
+async fn status(State(app): State<App>, ConnectInfo(peer): Peer, headers: HeaderMap) -> Response {
+    app.run(peer, &headers, Cmd::Status).await
+}
+
+async fn list_queue(
+    State(app): State<App>,
+    ConnectInfo(peer): Peer,
+    headers: HeaderMap,
+) -> Response {
+    app.run(peer, &headers, Cmd::ListQueue).await
+}
+
+async fn list_recent(
+    State(app): State<App>,
+    ConnectInfo(peer): Peer,
+    headers: HeaderMap,
+) -> Response {
+    app.run(peer, &headers, Cmd::ListRecent).await
+}
+
+async fn list_feeds(
+    State(app): State<App>,
+    ConnectInfo(peer): Peer,
+    headers: HeaderMap,
+) -> Response {
+    app.run(peer, &headers, Cmd::ListFeeds).await
+}
+
+async fn list_episodes(
+    State(app): State<App>,
+    ConnectInfo(peer): Peer,
+    headers: HeaderMap,
+) -> Response {
+    app.run(peer, &headers, Cmd::ListEpisodes).await
+}
+
+/// `GET /api/playable?uri=..`
+async fn get_playable(
+    State(app): State<App>,
+    ConnectInfo(peer): Peer,
+    headers: HeaderMap,
+    Query(mut query): Query<HashMap<String, String>>,
+) -> Response {
+    match query.remove("uri") {
+        Some(uri) => app.run(peer, &headers, Cmd::GetPlayable(uri)).await,
+        None => error(StatusCode::BAD_REQUEST, "missing uri".into()),
+    }
+}
+
+/// `POST /api/cmd/:method`, the body holds the params in the same form as in
+/// a JSON-RPC request.
+async fn command(
+    State(app): State<App>,
+    ConnectInfo(peer): Peer,
+    headers: HeaderMap,
+    Path(method): Path<String>,
+    body: Bytes,
+) -> Response {
+    if let Err((status, message)) = same_site_json(&headers) {
+        logln!("rejected http request from {peer}: {message}");
+        return error(status, message);
+    }
+    if let Err((status, message)) = app.authorize(peer, &headers, &method) {
+        return error(status, message);
+    }
+    let params = if body.iter().all(u8::is_ascii_whitespace) {
+        None
+    } else {
+        match serde_json::from_slice::<Value>(&body) {
+            Ok(params) => Some(params),
+            Err(err) => return error(StatusCode::BAD_REQUEST, format!("invalid body: {err}")),
+        }
+    };
+    let request = RpcRequest {
+        jsonrpc: "2.0".into(),
+        id: None,
+        method,
+        params,
+    };
+    match request.to_cmd() {
+        Ok(cmd) => app.execute(cmd).await,
+        Err(err) if err.code == rpc::METHOD_NOT_FOUND => error(StatusCode::NOT_FOUND, err.message),
+        Err(err) => error(StatusCode::BAD_REQUEST, err.message),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::HeaderValue;
+
+    use super::*;
+
+    #[test]
+    fn refuse_cross_site() {
+        let headers = |pairs: &[(header::HeaderName, &'static str)]| {
+            let mut headers = HeaderMap::new();
+            for (name, value) in pairs {
+                headers.insert(name, HeaderValue::from_static(value));
+            }
+            headers
+        };
+        let json = (header::CONTENT_TYPE, "application/json; charset=utf-8");
+        let host = (header::HOST, "localhost:8080");
+        assert!(same_site_json(&headers(&[json.clone()])).is_ok());
+        assert!(same_site_json(&headers(&[
+            json.clone(),
+            host.clone(),
+            (header::ORIGIN, "http://localhost:8080"),
+        ]))
+        .is_ok());
+
+        let refused = |pairs: &[(header::HeaderName, &'static str)]| {
+            same_site_json(&headers(pairs)).unwrap_err().0
+        };
+        assert_eq!(refused(&[]), StatusCode::UNSUPPORTED_MEDIA_TYPE);
+        assert_eq!(
+            refused(&[(header::CONTENT_TYPE, "text/plain")]),
+            StatusCode::UNSUPPORTED_MEDIA_TYPE
+        );
+        for origin in ["https://evil.example", "null"] {
+            assert_eq!(
+                refused(&[json.clone(), host.clone(), (header::ORIGIN, origin)]),
+                StatusCode::FORBIDDEN
+            );
+        }
+    }
+}
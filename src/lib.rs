@@ -1,4 +1,11 @@
+pub mod control;
+pub mod data;
 pub mod dir;
+pub mod events;
+pub mod gpodder;
+pub mod http;
+pub mod mpris;
+pub mod peers;
 pub mod player;
 
 #[macro_use]
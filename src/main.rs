@@ -2,92 +2,209 @@ use crossterm::event::{read, Event, KeyEvent};
 use crossterm::terminal::{EnterAlternateScreen, LeaveAlternateScreen};
 use crossterm::{execute, terminal};
 
+use podaemon::control::{
+    self,
+    auth::{self, Tokens},
+};
+use podaemon::gpodder;
+use podaemon::http;
 use podaemon::logln;
-use podaemon::player::{self, Cmd};
-use podaemon::ui::interface::UiUpdate;
+use podaemon::mpris;
+use podaemon::peers::Peers;
+use podaemon::player::{self, Cmd, Request};
+use podaemon::ui::{interface::UiUpdate, log, remote};
 // use rss::Channel;
-use tokio::io::AsyncReadExt;
 use tokio::net::TcpListener;
 use tokio::select;
 use tokio::signal::unix::{signal, SignalKind};
 use tokio::sync::mpsc::{self, Receiver, Sender};
 
 use std::env;
+use std::fs;
 use std::io::stdout;
+use std::sync::Arc;
+use std::time::Duration;
+
+const USAGE: &str = "\
+usage: podaemon [--daemon]
+       podaemon --attach [HOST:PORT] [--token TOKEN]
+
+  --daemon, --headless   run without the terminal interface, log to stderr
+  --attach               show the interface of a running podaemon, reached
+                         through its unix socket or at HOST:PORT. q detaches
+  --token TOKEN          authenticate the attached connection";
 
 #[tokio::main]
 async fn main() -> Result<(), std::io::Error> {
+    let mut headless = false;
+    let mut attach = None;
+    let mut token = None;
+    let mut args = env::args().skip(1).peekable();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--daemon" | "--headless" => headless = true,
+            "--attach" => attach = Some(args.next_if(|arg| !arg.starts_with('-'))),
+            "--token" => token = args.next(),
+            "-h" | "--help" => {
+                println!("{USAGE}");
+                return Ok(());
+            }
+            _ => {
+                eprintln!("podaemon: unknown argument: {arg}\n\n{USAGE}");
+                std::process::exit(2);
+            }
+        }
+    }
+
+    if let Some(host) = attach {
+        let (ui_tx, ui_rx) = mpsc::channel::<UiUpdate>(64);
+        enter_tui()?;
+        let _key_thread_handle = start_key_thread(ui_tx);
+        let result = remote::attach(host.as_deref(), token.as_deref(), ui_rx).await;
+        leave_tui()?;
+        if let Err(err) = result {
+            eprintln!("podaemon: {err}");
+            std::process::exit(1);
+        }
+        return Ok(());
+    }
+
     // console_subscriber::init();
+    if headless {
+        log::log_to_stderr();
+    }
     logln!("init");
     gstreamer::init().unwrap();
-    execute!(stdout(), EnterAlternateScreen)?;
 
-    let (tx, rx) = mpsc::channel::<Cmd>(64);
+    let (tx, rx) = mpsc::channel::<Request>(64);
     let tx2 = tx.clone();
     let tx3 = tx2.clone();
-    let (ui_tx, ui_rx) = mpsc::channel::<UiUpdate>(64);
 
     tokio::spawn(async move {
         sig_handler(tx2).await.unwrap();
     });
 
+    let socket_path = control::socket_path();
+    let unix_listener = control::bind_unix(&socket_path).await;
+    let bound_unix = unix_listener.is_some();
+    if let Some(listener) = unix_listener {
+        tokio::spawn(control::listen_unix(tx.clone(), listener));
+    }
+
+    tokio::spawn(mpris::serve(tx.clone()));
+    if let Some(config) = gpodder::Config::from_env() {
+        tokio::spawn(gpodder::run(config, tx.clone()));
+    }
+
+    let bind_addr = env::var("BIND_ADDR").unwrap_or_else(|_| "127.0.0.1".into());
+    let tokens = Tokens::from_env().map(Arc::new);
+    if let Ok(port) = env::var("HTTP_PORT") {
+        let (queue, bind_addr, tokens) = (tx.clone(), bind_addr.clone(), tokens.clone());
+        tokio::spawn(async move {
+            http::serve(queue, &bind_addr, &port, tokens).await;
+        });
+    }
     if let Ok(port) = env::var("PORT") {
-        let addr = format!("0.0.0.0:{port}");
         tokio::spawn(async move {
-            listen(tx, &addr).await;
+            listen(tx, &bind_addr, &port, tokens).await;
         });
     }
+
+    let autosave = autosave_interval();
+    let result = if headless {
+        ploop(rx, tx3, None, autosave).await
+    } else {
+        let (ui_tx, ui_rx) = mpsc::channel::<UiUpdate>(64);
+        enter_tui()?;
+        let _key_thread_handle = start_key_thread(ui_tx);
+        let result = ploop(rx, tx3, Some(ui_rx), autosave).await;
+        leave_tui()?;
+        result
+    };
+    if bound_unix {
+        let _ = fs::remove_file(&socket_path);
+    }
+    if let Err(err) = result {
+        eprintln!("podaemon: {err}");
+        std::process::exit(1);
+    }
+    Ok(())
+}
+
+fn enter_tui() -> Result<(), std::io::Error> {
+    execute!(stdout(), EnterAlternateScreen)?;
     if let Err(err) = terminal::enable_raw_mode() {
         logln!("{err}");
     };
+    Ok(())
+}
 
-    let _key_thread_handle = start_key_thread(ui_tx);
-    ploop(rx, tx3, ui_rx).await;
-
+fn leave_tui() -> Result<(), std::io::Error> {
     if let Err(err) = terminal::disable_raw_mode() {
         logln!("{err}");
     };
+    execute!(stdout(), LeaveAlternateScreen)
+}
 
-    execute!(stdout(), LeaveAlternateScreen)?;
-    Ok(())
+/// `SAVE_INTERVAL` in seconds, 120 by default. `0` turns periodic saving off.
+fn autosave_interval() -> Option<Duration> {
+    let secs = match env::var("SAVE_INTERVAL") {
+        Ok(secs) => secs.parse().unwrap_or_else(|_| {
+            logln!("invalid SAVE_INTERVAL: {secs}");
+            120
+        }),
+        Err(_) => 120,
+    };
+    (secs > 0).then(|| Duration::from_secs(secs))
 }
 
-async fn ploop(mut queue: Receiver<Cmd>, tx: Sender<Cmd>, ui_rx: Receiver<UiUpdate>) {
-    let p: Sender<Cmd> = player::new(ui_rx, tx).await;
+async fn ploop(
+    mut queue: Receiver<Request>,
+    tx: Sender<Request>,
+    ui_rx: Option<Receiver<UiUpdate>>,
+    autosave: Option<Duration>,
+) -> Result<(), String> {
+    let p: Sender<Request> = player::new(ui_rx, autosave, Peers::from_env(), tx).await?;
     while let Some(cmd) = queue.recv().await {
-        if cmd == Cmd::Shutdown {
+        if cmd.cmd == Cmd::Shutdown {
             if let Err(err) = p.send(cmd).await {
                 logln!("{err}");
             }
             p.closed().await;
-            return;
+            return Ok(());
         }
 
         if let Err(err) = p.send(cmd).await {
             logln!("{err}");
         }
     }
+    Ok(())
 }
 
-async fn listen(queue: Sender<Cmd>, addr: &str) {
-    let listener = TcpListener::bind(addr).await.unwrap();
-    logln!("listening on: {}", listener.local_addr().unwrap());
-
-    let mut buf = String::new();
-    while let Ok((mut socket, _addr)) = listener.accept().await {
-        match socket.read_to_string(&mut buf).await {
-            Ok(_n) => {
-                for line in buf.lines() {
-                    if let Some(cmd) = player::parse_cmd(line) {
-                        if let Err(msg) = queue.send(cmd).await {
-                            logln!("receiver dropped: {}", msg);
-                        }
-                    }
-                }
-            }
-            Err(e) => print!("Err: {}", e),
+async fn listen(queue: Sender<Request>, bind_addr: &str, port: &str, tokens: Option<Arc<Tokens>>) {
+    let Ok(port) = port.parse::<u16>() else {
+        logln!("invalid PORT: {port}");
+        return;
+    };
+    let listener = match TcpListener::bind((bind_addr, port)).await {
+        Ok(listener) => listener,
+        Err(err) => {
+            logln!("failed to bind {bind_addr}:{port}: {err}");
+            return;
         }
-        buf.clear();
+    };
+    let local_addr = listener.local_addr().unwrap();
+    logln!("listening on: {local_addr}");
+
+    auth::warn_unprotected(tokens.as_deref(), local_addr);
+    while let Ok((socket, addr)) = listener.accept().await {
+        logln!("control connection from {addr}");
+        tokio::spawn(control::serve(
+            socket,
+            queue.clone(),
+            addr.to_string(),
+            tokens.clone(),
+        ));
     }
     logln!("loop ended");
 }
@@ -113,22 +230,22 @@ fn start_key_thread(ui_tx: Sender<UiUpdate>) -> std::thread::JoinHandle<()> {
     })
 }
 
-async fn sig_handler(cmd: Sender<Cmd>) -> Result<(), Box<dyn std::error::Error>> {
+async fn sig_handler(cmd: Sender<Request>) -> Result<(), Box<dyn std::error::Error>> {
     let mut sigint = signal(SignalKind::interrupt())?;
     let mut sigquit = signal(SignalKind::quit())?;
     let mut sigterm = signal(SignalKind::terminate())?;
     loop {
         select! {
             Some(()) = sigterm.recv() => {
-                cmd.send(Cmd::Shutdown).await?;
+                cmd.send(Cmd::Shutdown.into()).await?;
                 break;
             }
             Some(()) = sigquit.recv() => {
-                cmd.send(Cmd::Shutdown).await?;
+                cmd.send(Cmd::Shutdown.into()).await?;
                 break;
             }
             Some(()) = sigint.recv() => {
-                cmd.send(Cmd::Shutdown).await?;
+                cmd.send(Cmd::Shutdown.into()).await?;
                 break;
             }
         }
@@ -0,0 +1,602 @@
+//! MPRIS2 D-Bus interface, lets media keys, `playerctl` and desktop widgets
+//! control the player. Every call is translated into a [`Cmd`] and sent over
+//! the same channel as the control socket uses.
+
+use std::{
+    collections::hash_map::DefaultHasher,
+    collections::HashMap,
+    hash::{Hash, Hasher},
+};
+
+use serde::de::DeserializeOwned;
+use tokio::sync::{broadcast::error::RecvError, mpsc::Sender};
+use zbus::{
+    dbus_interface, fdo,
+    zvariant::{ObjectPath, OwnedObjectPath, OwnedValue, Value},
+    Connection, ConnectionBuilder, SignalContext,
+};
+
+use crate::{
+    control,
+    events::{self, Event},
+    logln,
+    player::{
+        query::{Entry, Status},
+        Cmd, Request,
+    },
+};
+
+pub const BUS_NAME: &str = "org.mpris.MediaPlayer2.podaemon";
+const PATH: &str = "/org/mpris/MediaPlayer2";
+const TRACK_PREFIX: &str = "/org/podaemon/track";
+const NO_TRACK: &str = "/org/mpris/MediaPlayer2/TrackList/NoTrack";
+
+type Metadata = HashMap<String, OwnedValue>;
+
+/// Claims [`BUS_NAME`] on the session bus and keeps the exported properties
+/// up to date until the connection is lost.
+pub async fn serve(queue: Sender<Request>) {
+    let connection = match connect(queue).await {
+        Ok(connection) => connection,
+        Err(err) => {
+            logln!("mpris unavailable: {err}");
+            return;
+        }
+    };
+    logln!("mpris: registered {BUS_NAME}");
+    if let Err(err) = forward_events(&connection).await {
+        logln!("mpris: {err}");
+    }
+}
+
+pub async fn connect(queue: Sender<Request>) -> zbus::Result<Connection> {
+    register(ConnectionBuilder::session()?, queue).await
+}
+
+async fn register(
+    builder: ConnectionBuilder<'_>,
+    queue: Sender<Request>,
+) -> zbus::Result<Connection> {
+    builder
+        .name(BUS_NAME)?
+        .serve_at(
+            PATH,
+            Root {
+                queue: queue.clone(),
+            },
+        )?
+        .serve_at(
+            PATH,
+            MprisPlayer {
+                queue: queue.clone(),
+            },
+        )?
+        .serve_at(PATH, TrackList { queue })?
+        .build()
+        .await
+}
+
+/// Turns player events into `PropertiesChanged` and `Seeked` signals.
+async fn forward_events(connection: &Connection) -> zbus::Result<()> {
+    let server = connection.object_server();
+    let player = server.interface::<_, MprisPlayer>(PATH).await?;
+    let track_list = server.interface::<_, TrackList>(PATH).await?;
+    let ctxt = SignalContext::new(connection, PATH)?;
+
+    let mut events = events::subscribe();
+    let mut last_position = None;
+    loop {
+        let event = match events.recv().await {
+            Ok(event) => event,
+            Err(RecvError::Lagged(_)) => continue,
+            Err(RecvError::Closed) => return Ok(()),
+        };
+        match event {
+            Event::TrackChanged { .. } => {
+                last_position = None;
+                player.get().await.metadata_changed(&ctxt).await?;
+            }
+            Event::PlayState { .. } | Event::Finished { .. } => {
+                player.get().await.playback_status_changed(&ctxt).await?;
+            }
+            Event::QueueChanged { .. } => {
+                track_list.get().await.tracks_changed(&ctxt).await?;
+            }
+            Event::Position { position, .. } => {
+                // positions arrive once a second, anything else was a seek
+                let jumped = last_position
+                    .map(|last: u64| position.abs_diff(last) > 2)
+                    .unwrap_or(false);
+                if jumped {
+                    MprisPlayer::seeked(&ctxt, seconds_to_us(position)).await?;
+                }
+                last_position = Some(position);
+            }
+            Event::FeedRefreshing { .. }
+            | Event::FeedRefreshed { .. }
+            | Event::NewEpisodes { .. }
+            | Event::Unsubscribed { .. }
+            | Event::Error { .. } => {}
+        }
+    }
+}
+
+async fn run(queue: &Sender<Request>, cmd: Cmd) -> fdo::Result<()> {
+    control::execute(cmd, queue)
+        .await
+        .map(|_| ())
+        .map_err(fdo::Error::Failed)
+}
+
+async fn query<T: DeserializeOwned>(queue: &Sender<Request>, cmd: Cmd) -> fdo::Result<T> {
+    let value = control::execute(cmd, queue)
+        .await
+        .map_err(fdo::Error::Failed)?
+        .unwrap_or_default();
+    serde_json::from_value(value).map_err(|err| fdo::Error::Failed(err.to_string()))
+}
+
+async fn status(queue: &Sender<Request>) -> fdo::Result<Status> {
+    query(queue, Cmd::Status).await
+}
+
+/// Track ids only need to be stable while the daemon runs.
+fn track_id(uri: &str) -> OwnedObjectPath {
+    let mut hasher = DefaultHasher::new();
+    uri.hash(&mut hasher);
+    let path = format!("{TRACK_PREFIX}/t{:016x}", hasher.finish());
+    ObjectPath::try_from(path)
+        .expect("track ids are valid object paths")
+        .into()
+}
+
+fn seconds_to_us(seconds: u64) -> i64 {
+    i64::try_from(seconds.saturating_mul(1_000_000)).unwrap_or(i64::MAX)
+}
+
+/// Rounded to the nearest second, the player seeks in whole seconds.
+fn us_to_seconds(us: i64) -> i64 {
+    us.saturating_add(500_000 * us.signum()) / 1_000_000
+}
+
+fn metadata(uri: &str, title: Option<&str>, album: Option<&str>, length: Option<u64>) -> Metadata {
+    let mut metadata = Metadata::new();
+    let mut insert = |key: &str, value: Value| {
+        metadata.insert(key.to_string(), value.into());
+    };
+    insert("mpris:trackid", Value::from(track_id(uri).into_inner()));
+    insert("xesam:url", Value::from(uri));
+    insert("xesam:title", Value::from(title.unwrap_or(uri)));
+    if let Some(album) = album {
+        insert("xesam:album", Value::from(album));
+    }
+    if let Some(length) = length {
+        insert("mpris:length", Value::from(seconds_to_us(length)));
+    }
+    metadata
+}
+
+fn entry_metadata(entry: &Entry) -> Metadata {
+    let playable = entry.playable.as_ref();
+    metadata(
+        &entry.uri,
+        playable.and_then(|p| p.title.as_deref()),
+        playable.and_then(|p| p.album.as_deref()),
+        playable.and_then(|p| p.length),
+    )
+}
+
+struct Root {
+    queue: Sender<Request>,
+}
+
+#[dbus_interface(name = "org.mpris.MediaPlayer2")]
+impl Root {
+    async fn raise(&self) {}
+
+    async fn quit(&self) -> fdo::Result<()> {
+        run(&self.queue, Cmd::Shutdown).await
+    }
+
+    #[dbus_interface(property)]
+    fn can_quit(&self) -> bool {
+        true
+    }
+
+    #[dbus_interface(property)]
+    fn can_raise(&self) -> bool {
+        false
+    }
+
+    #[dbus_interface(property)]
+    fn has_track_list(&self) -> bool {
+        true
+    }
+
+    #[dbus_interface(property)]
+    fn identity(&self) -> &str {
+        "podaemon"
+    }
+
+    #[dbus_interface(property)]
+    fn supported_uri_schemes(&self) -> Vec<&str> {
+        vec!["file", "http", "https"]
+    }
+
+    #[dbus_interface(property)]
+    fn supported_mime_types(&self) -> Vec<&str> {
+        vec![
+            "audio/mpeg",
+            "audio/ogg",
+            "audio/mp4",
+            "audio/aac",
+            "audio/flac",
+        ]
+    }
+}
+
+struct MprisPlayer {
+    queue: Sender<Request>,
+}
+
+#[dbus_interface(name = "org.mpris.MediaPlayer2.Player")]
+impl MprisPlayer {
+    async fn next(&self) -> fdo::Result<()> {
+        run(&self.queue, Cmd::Next).await
+    }
+
+    async fn previous(&self) -> fdo::Result<()> {
+        run(&self.queue, Cmd::Prev).await
+    }
+
+    async fn pause(&self) -> fdo::Result<()> {
+        run(&self.queue, Cmd::Pause).await
+    }
+
+    async fn play_pause(&self) -> fdo::Result<()> {
+        run(&self.queue, Cmd::PlayPause).await
+    }
+
+    /// There is no stopped state that keeps the position, pausing is closest.
+    async fn stop(&self) -> fdo::Result<()> {
+        run(&self.queue, Cmd::Pause).await
+    }
+
+    async fn play(&self) -> fdo::Result<()> {
+        run(&self.queue, Cmd::Play).await
+    }
+
+    async fn seek(&self, offset: i64) -> fdo::Result<()> {
+        run(&self.queue, Cmd::SeekRelative(us_to_seconds(offset))).await
+    }
+
+    async fn set_position(&self, track_id: ObjectPath<'_>, position: i64) -> fdo::Result<()> {
+        let status = status(&self.queue).await?;
+        let current = status.uri.as_deref().map(track_id_of);
+        // stale requests for another track are to be ignored, as are positions past its end
+        let end = status.duration.map_or(i64::MAX, seconds_to_us);
+        if current.as_deref() != Some(track_id.as_str()) || position < 0 || position > end {
+            return Ok(());
+        }
+        run(&self.queue, Cmd::Seek(us_to_seconds(position) as u64)).await
+    }
+
+    async fn open_uri(&self, uri: String) -> fdo::Result<()> {
+        run(&self.queue, Cmd::Queue(uri)).await
+    }
+
+    #[dbus_interface(signal)]
+    async fn seeked(ctxt: &SignalContext<'_>, position: i64) -> zbus::Result<()>;
+
+    #[dbus_interface(property)]
+    async fn playback_status(&self) -> fdo::Result<String> {
+        let status = status(&self.queue).await?;
+        let playback_status = match (status.playing, status.uri) {
+            (true, _) => "Playing",
+            (false, Some(_)) if status.state == "Paused" => "Paused",
+            _ => "Stopped",
+        };
+        Ok(playback_status.into())
+    }
+
+    #[dbus_interface(property)]
+    async fn metadata(&self) -> fdo::Result<Metadata> {
+        let status = status(&self.queue).await?;
+        Ok(match &status.uri {
+            Some(uri) => metadata(
+                uri,
+                status.title.as_deref(),
+                status.album.as_deref(),
+                status.duration,
+            ),
+            None => {
+                let mut metadata = Metadata::new();
+                let no_track = ObjectPath::from_static_str_unchecked(NO_TRACK);
+                metadata.insert("mpris:trackid".into(), Value::from(no_track).into());
+                metadata
+            }
+        })
+    }
+
+    /// Position in microseconds, changes are announced by `Seeked` only.
+    #[dbus_interface(property)]
+    async fn position(&self) -> fdo::Result<i64> {
+        let status = status(&self.queue).await?;
+        Ok(seconds_to_us(status.position.unwrap_or_default()))
+    }
+
+    #[dbus_interface(property)]
+    fn rate(&self) -> f64 {
+        1.0
+    }
+
+    #[dbus_interface(property)]
+    fn set_rate(&self, _rate: f64) {}
+
+    #[dbus_interface(property)]
+    fn minimum_rate(&self) -> f64 {
+        1.0
+    }
+
+    #[dbus_interface(property)]
+    fn maximum_rate(&self) -> f64 {
+        1.0
+    }
+
+    #[dbus_interface(property)]
+    fn can_go_next(&self) -> bool {
+        true
+    }
+
+    #[dbus_interface(property)]
+    fn can_go_previous(&self) -> bool {
+        true
+    }
+
+    #[dbus_interface(property)]
+    fn can_play(&self) -> bool {
+        true
+    }
+
+    #[dbus_interface(property)]
+    fn can_pause(&self) -> bool {
+        true
+    }
+
+    #[dbus_interface(property)]
+    fn can_seek(&self) -> bool {
+        true
+    }
+
+    #[dbus_interface(property)]
+    fn can_control(&self) -> bool {
+        true
+    }
+}
+
+fn track_id_of(uri: &str) -> String {
+    track_id(uri).as_str().to_string()
+}
+
+/// The current track followed by the queue.
+struct TrackList {
+    queue: Sender<Request>,
+}
+
+impl TrackList {
+    async fn entries(&self) -> fdo::Result<Vec<Entry>> {
+        let status = status(&self.queue).await?;
+        let mut entries: Vec<Entry> = query(&self.queue, Cmd::ListQueue).await?;
+        if let Some(uri) = status.uri {
+            let playable = query(&self.queue, Cmd::GetPlayable(uri.clone())).await.ok();
+            entries.insert(0, Entry { uri, playable });
+        }
+        Ok(entries)
+    }
+
+    /// The uri of the queued track `track_id`, commands take it rather than
+    /// an index that may be stale by the time they run.
+    async fn queued_uri(&self, track_id: &ObjectPath<'_>) -> fdo::Result<String> {
+        let queued = query::<Vec<Entry>>(&self.queue, Cmd::ListQueue).await?;
+        queued
+            .into_iter()
+            .find(|e| track_id_of(&e.uri) == track_id.as_str())
+            .map(|e| e.uri)
+            .ok_or_else(|| fdo::Error::InvalidArgs(format!("not queued: {track_id}")))
+    }
+}
+
+#[dbus_interface(name = "org.mpris.MediaPlayer2.TrackList")]
+impl TrackList {
+    async fn get_tracks_metadata(
+        &self,
+        track_ids: Vec<OwnedObjectPath>,
+    ) -> fdo::Result<Vec<Metadata>> {
+        let entries = self.entries().await?;
+        Ok(track_ids
+            .iter()
+            .filter_map(|id| entries.iter().find(|e| track_id(&e.uri) == *id))
+            .map(entry_metadata)
+            .collect())
+    }
+
+    /// Tracks can only be appended to the queue, `after_track` is ignored.
+    /// A track set as current is played right away instead.
+    async fn add_track(
+        &self,
+        uri: String,
+        _after_track: ObjectPath<'_>,
+        set_as_current: bool,
+    ) -> fdo::Result<()> {
+        if set_as_current {
+            run(&self.queue, Cmd::PlayNow(uri)).await
+        } else {
+            run(&self.queue, Cmd::Queue(uri)).await
+        }
+    }
+
+    async fn remove_track(&self, track_id: ObjectPath<'_>) -> fdo::Result<()> {
+        let uri = self.queued_uri(&track_id).await?;
+        run(&self.queue, Cmd::Dequeue(uri)).await
+    }
+
+    /// Plays `track_id` right away, taking it out of the queue.
+    async fn go_to(&self, track_id: ObjectPath<'_>) -> fdo::Result<()> {
+        let uri = self.queued_uri(&track_id).await?;
+        run(&self.queue, Cmd::Dequeue(uri.clone())).await?;
+        run(&self.queue, Cmd::PlayNow(uri)).await
+    }
+
+    #[dbus_interface(property)]
+    async fn tracks(&self) -> fdo::Result<Vec<OwnedObjectPath>> {
+        let entries = self.entries().await?;
+        Ok(entries.iter().map(|e| track_id(&e.uri)).collect())
+    }
+
+    #[dbus_interface(property)]
+    fn can_edit_tracks(&self) -> bool {
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io::{BufRead, BufReader},
+        process::{Child, Command, Stdio},
+        sync::{Arc, Mutex},
+    };
+
+    use serde_json::json;
+    use tokio::sync::mpsc;
+    use zbus::Proxy;
+
+    use super::*;
+
+    /// A `dbus-daemon` of our own, stopped when dropped.
+    struct Bus(Child);
+
+    impl Drop for Bus {
+        fn drop(&mut self) {
+            let _ = self.0.kill();
+            let _ = self.0.wait();
+        }
+    }
+
+    fn private_bus() -> Option<(Bus, String)> {
+        let child = Command::new("dbus-daemon")
+            .args(["--session", "--nofork", "--print-address"])
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()
+            .ok()?;
+        let mut bus = Bus(child);
+        let mut address = String::new();
+        BufReader::new(bus.0.stdout.take()?)
+            .read_line(&mut address)
+            .ok()?;
+        Some((bus, address.trim().to_string()))
+    }
+
+    fn fake_player() -> (Sender<Request>, Arc<Mutex<Vec<Cmd>>>) {
+        let status = json!({
+            "state": "Playing", "playing": true, "uri": "http://a/1.mp3",
+            "title": "One", "album": "A", "position": 30, "duration": 600, "queue_len": 1,
+        });
+        let queue = json!([{ "uri": "http://a/2.mp3", "playable": null }]);
+        let (tx, mut rx) = mpsc::channel::<Request>(8);
+        let received = Arc::new(Mutex::new(Vec::new()));
+        let log = Arc::clone(&received);
+        tokio::spawn(async move {
+            while let Some(Request { cmd, reply }) = rx.recv().await {
+                let response = match cmd {
+                    Cmd::Status => Some(status.clone()),
+                    Cmd::ListQueue => Some(queue.clone()),
+                    _ => None,
+                };
+                log.lock().unwrap().push(cmd);
+                reply.send(Ok(response));
+            }
+        });
+        (tx, received)
+    }
+
+    #[tokio::test]
+    async fn player_and_track_list() {
+        let Some((_bus, address)) = private_bus() else {
+            eprintln!("skipping, no dbus-daemon");
+            return;
+        };
+        let (queue, received) = fake_player();
+        let builder = ConnectionBuilder::address(address.as_str()).unwrap();
+        let _server = register(builder, queue).await.unwrap();
+        let client = ConnectionBuilder::address(address.as_str())
+            .unwrap()
+            .build()
+            .await
+            .unwrap();
+        let proxy = |interface| Proxy::new(&client, BUS_NAME, PATH, interface);
+
+        let player = proxy("org.mpris.MediaPlayer2.Player").await.unwrap();
+        player.call_method("PlayPause", &()).await.unwrap();
+        player.call_method("Seek", &(-1_600_000i64)).await.unwrap();
+        player.call_method("Seek", &i64::MAX).await.unwrap();
+        let current = track_id("http://a/1.mp3");
+        player
+            .call_method("SetPosition", &(&current, i64::MAX))
+            .await
+            .unwrap();
+        player
+            .call_method("SetPosition", &(&current, 60_000_000i64))
+            .await
+            .unwrap();
+        let metadata: Metadata = player.get_property("Metadata").await.unwrap();
+        let text = |key: &str| String::try_from(metadata[key].clone()).unwrap();
+        assert_eq!(text("xesam:title"), "One");
+        assert_eq!(text("xesam:album"), "A");
+        assert_eq!(
+            i64::try_from(metadata["mpris:length"].clone()),
+            Ok(600_000_000)
+        );
+        let status: String = player.get_property("PlaybackStatus").await.unwrap();
+        assert_eq!(status, "Playing");
+
+        let track_list = proxy("org.mpris.MediaPlayer2.TrackList").await.unwrap();
+        let tracks: Vec<OwnedObjectPath> = track_list.get_property("Tracks").await.unwrap();
+        // the current track comes first
+        assert_eq!(
+            tracks,
+            [track_id("http://a/1.mp3"), track_id("http://a/2.mp3")]
+        );
+        let no_track = ObjectPath::from_static_str_unchecked(NO_TRACK);
+        track_list
+            .call_method("AddTrack", &("http://a/3.mp3", &no_track, true))
+            .await
+            .unwrap();
+        let queued = track_id("http://a/2.mp3");
+        track_list
+            .call_method("RemoveTrack", &(&queued,))
+            .await
+            .unwrap();
+        track_list.call_method("GoTo", &(&queued,)).await.unwrap();
+
+        let received = received.lock().unwrap();
+        let commands: Vec<&Cmd> = received
+            .iter()
+            .filter(|cmd| !matches!(cmd, Cmd::Status | Cmd::ListQueue | Cmd::GetPlayable(_)))
+            .collect();
+        assert_eq!(
+            commands,
+            [
+                &Cmd::PlayPause,
+                &Cmd::SeekRelative(-2),
+                &Cmd::SeekRelative(us_to_seconds(i64::MAX)),
+                &Cmd::Seek(60),
+                &Cmd::PlayNow("http://a/3.mp3".into()),
+                &Cmd::Dequeue("http://a/2.mp3".into()),
+                &Cmd::Dequeue("http://a/2.mp3".into()),
+                &Cmd::PlayNow("http://a/2.mp3".into()),
+            ]
+        );
+    }
+}
@@ -0,0 +1,270 @@
+//! Pushes playback progress to other podaemon instances, so that one can
+//! resume where the other left off. `PEERS` lists them separated by
+//! whitespace as `host:port` of their control listener, or `token@host:port`
+//! if they require a token.
+//!
+//! Updates are sent as `update(uri,progress,length,time)` commands. Every
+//! peer has its own task that keeps only the latest update per uri and
+//! retries with a growing delay while the peer is unreachable. On shutdown
+//! [`Peers::flush`] makes a last attempt and hands back what is still unsent,
+//! the player keeps it in its store and [resumes](Peers::resume) it on start.
+
+use std::{collections::HashMap, env, time::Duration};
+
+use futures::future::join_all;
+use tokio::{
+    io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+    select,
+    sync::mpsc::{
+        self,
+        error::{TryRecvError, TrySendError},
+        Receiver, Sender,
+    },
+    task::JoinHandle,
+    time,
+};
+
+use crate::{control, logln, player::UpdateArgs};
+
+const MIN_RETRY: Duration = Duration::from_secs(1);
+const MAX_RETRY: Duration = Duration::from_secs(300);
+const PUSH_TIMEOUT: Duration = Duration::from_secs(10);
+/// How long the last attempt on shutdown may take.
+const FLUSH_TIMEOUT: Duration = Duration::from_secs(3);
+
+/// The latest update per uri that a peer has not acknowledged yet.
+type Pending = HashMap<String, UpdateArgs>;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Peer {
+    pub addr: String,
+    pub token: Option<String>,
+}
+
+impl Peer {
+    pub fn parse(entry: &str) -> Self {
+        match entry.rsplit_once('@') {
+            Some((token, addr)) => Peer {
+                addr: addr.to_string(),
+                token: Some(token.to_string()),
+            },
+            None => Peer {
+                addr: entry.to_string(),
+                token: None,
+            },
+        }
+    }
+}
+
+/// Handle to the push tasks of all peers, empty if there are none.
+#[derive(Debug, Default)]
+pub struct Peers(Vec<(String, Sender<UpdateArgs>, JoinHandle<Pending>)>);
+
+impl Peers {
+    pub fn from_env() -> Self {
+        let peers = env::var("PEERS").unwrap_or_default();
+        Peers::start(peers.split_whitespace().map(Peer::parse).collect())
+    }
+
+    pub fn start(peers: Vec<Peer>) -> Self {
+        let peers = peers
+            .into_iter()
+            .map(|peer| {
+                let (tx, rx) = mpsc::channel(64);
+                logln!("pushing progress to {}", peer.addr);
+                let addr = peer.addr.clone();
+                (addr, tx, tokio::spawn(push_loop(peer, rx)))
+            })
+            .collect();
+        Peers(peers)
+    }
+
+    /// Queues `update` for every peer, never waits for them.
+    pub fn push(&self, update: &UpdateArgs) {
+        for (addr, tx, _) in &self.0 {
+            queue(addr, tx, update.clone());
+        }
+    }
+
+    /// Queues updates left over from the last run, `(peer, update)` as
+    /// returned by [`Peers::flush`]. Those for peers no longer configured are
+    /// dropped.
+    pub fn resume(&self, unsent: Vec<(String, UpdateArgs)>) {
+        for (peer, update) in unsent {
+            match self.0.iter().find(|(addr, _, _)| *addr == peer) {
+                Some((addr, tx, _)) => queue(addr, tx, update),
+                None => logln!("dropping update for {peer}, it is no longer a peer"),
+            }
+        }
+    }
+
+    /// Stops the push tasks after a last attempt to deliver what they have,
+    /// returns the updates that could not be delivered as `(peer, update)`.
+    pub async fn flush(&mut self) -> Vec<(String, UpdateArgs)> {
+        // closing the channels ends the tasks, all of them at once
+        let tasks: Vec<_> = self
+            .0
+            .drain(..)
+            .map(|(addr, _, task)| (addr, task))
+            .collect();
+        // a push may be in flight before the last attempt
+        let deadline = time::Instant::now() + PUSH_TIMEOUT + FLUSH_TIMEOUT;
+        let results = join_all(
+            tasks
+                .into_iter()
+                .map(|(addr, task)| async move { (addr, time::timeout_at(deadline, task).await) }),
+        )
+        .await;
+        let mut unsent = Vec::new();
+        for (addr, result) in results {
+            match result {
+                Ok(Ok(pending)) => {
+                    if !pending.is_empty() {
+                        logln!("keeping {} updates for {addr}", pending.len());
+                    }
+                    unsent.extend(pending.into_values().map(|update| (addr.clone(), update)));
+                }
+                Ok(Err(err)) => logln!("push task for {addr} failed: {err}"),
+                Err(_) => logln!("gave up waiting for the push task for {addr}"),
+            }
+        }
+        unsent
+    }
+}
+
+fn queue(addr: &str, tx: &Sender<UpdateArgs>, update: UpdateArgs) {
+    match tx.try_send(update) {
+        Ok(()) => {}
+        Err(TrySendError::Full(_)) => logln!("dropping update for {addr}, too many queued"),
+        Err(TrySendError::Closed(_)) => logln!("push task for {addr} is gone"),
+    }
+}
+
+/// Pushes updates until `rx` is closed, then makes a last attempt and
+/// returns what is left.
+async fn push_loop(peer: Peer, mut rx: Receiver<UpdateArgs>) -> Pending {
+    let mut pending = Pending::new();
+    let mut retry = MIN_RETRY;
+    'open: loop {
+        if pending.is_empty() {
+            match rx.recv().await {
+                Some(update) => {
+                    pending.insert(update.0.clone(), update);
+                }
+                None => break,
+            }
+        }
+        // take whatever else is already queued before connecting
+        loop {
+            match rx.try_recv() {
+                Ok(update) => {
+                    pending.insert(update.0.clone(), update);
+                }
+                Err(TryRecvError::Empty) => break,
+                Err(TryRecvError::Disconnected) => break 'open,
+            }
+        }
+
+        if push(&peer, &mut pending, PUSH_TIMEOUT).await {
+            retry = MIN_RETRY;
+            continue;
+        }
+
+        // keep collecting updates while waiting to try again
+        let sleep = time::sleep(retry);
+        tokio::pin!(sleep);
+        loop {
+            select! {
+                _ = &mut sleep => break,
+                update = rx.recv() => match update {
+                    Some(update) => {
+                        pending.insert(update.0.clone(), update);
+                    }
+                    None => break 'open,
+                },
+            }
+        }
+        retry = (retry * 2).min(MAX_RETRY);
+    }
+    if !pending.is_empty() {
+        push(&peer, &mut pending, FLUSH_TIMEOUT).await;
+    }
+    pending
+}
+
+/// [`send`] within `timeout`, whether everything was delivered.
+async fn push(peer: &Peer, pending: &mut Pending, timeout: Duration) -> bool {
+    match time::timeout(timeout, send(peer, pending)).await {
+        Ok(Ok(())) => true,
+        Ok(Err(err)) => {
+            logln!("failed to push progress to {}: {err}", peer.addr);
+            false
+        }
+        Err(_) => {
+            logln!("failed to push progress to {}: timed out", peer.addr);
+            false
+        }
+    }
+}
+
+/// Sends all `pending` updates over one connection, removing those the peer
+/// answered. Updates the peer refused are dropped, retrying would not help.
+async fn send(peer: &Peer, pending: &mut Pending) -> Result<(), String> {
+    let stream = control::connect(Some(&peer.addr), peer.token.as_deref()).await?;
+    let (reader, mut writer) = tokio::io::split(stream);
+    let mut lines = BufReader::new(reader).lines();
+    let io_err = |err| format!("connection error: {err}");
+
+    let uris: Vec<String> = pending.keys().cloned().collect();
+    for uri in uris {
+        let Some(line) = pending.get(&uri).and_then(UpdateArgs::to_cmd_string) else {
+            pending.remove(&uri);
+            continue;
+        };
+        writer
+            .write_all(format!("{line}\n").as_bytes())
+            .await
+            .map_err(io_err)?;
+        match lines.next_line().await.map_err(io_err)? {
+            Some(reply) if reply == "ok" => {}
+            Some(reply) => logln!("{} refused {line}: {reply}", peer.addr),
+            None => return Err("connection closed".into()),
+        }
+        pending.remove(&uri);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::player::state::Playable;
+
+    #[tokio::test]
+    async fn flush_returns_unsent() {
+        // nothing listens there once the listener is dropped
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        drop(listener);
+
+        let mut peers = Peers::start(vec![Peer::parse(&addr)]);
+        let update = UpdateArgs(
+            "ep1".into(),
+            Playable {
+                progress: Some(30),
+                updated: Some(1_700_000_000),
+                ..Playable::default()
+            },
+        );
+        peers.push(&update);
+        assert_eq!(peers.flush().await, [(addr.clone(), update.clone())]);
+
+        // kept for the next start, unless the peer is gone by then
+        let mut peers = Peers::start(vec![Peer::parse(&addr)]);
+        peers.resume(vec![
+            (addr.clone(), update.clone()),
+            ("gone:7777".into(), update.clone()),
+        ]);
+        assert_eq!(peers.flush().await, [(addr, update)]);
+    }
+}
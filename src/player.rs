@@ -1,6 +1,12 @@
 mod backend;
+mod cache;
 mod cmd;
+mod fetch;
+pub mod opml;
+pub mod query;
 pub mod state;
+pub mod stats;
+mod store;
 
 pub use crate::player::backend::new;
 pub use crate::player::backend::Player;
@@ -1,25 +1,41 @@
 use crate::logln;
+use ratatui::{backend::CrosstermBackend, Terminal};
+use serde::Serialize;
 use std::{
+    collections::{BTreeSet, HashSet},
     error::Error,
-    sync::{Arc, RwLock},
+    future,
+    io::Stdout,
+    sync::{Arc, Mutex},
     time::Duration,
 };
 use tokio::{
     select,
-    sync::mpsc::{Receiver, Sender},
-    time,
+    sync::{
+        broadcast::{self, error::RecvError},
+        mpsc::{Receiver, Sender},
+    },
+    time::{self, Interval},
 };
 use tokio_stream::StreamExt;
-use ratatui::{backend::CrosstermBackend, Terminal};
 
 use crate::{
+    events::{self, Event},
+    peers::Peers,
     player::state::get_time,
-    ui::interface::{draw_ui, UiState, UiUpdate},
+    ui::{
+        interface::{draw_ui, UiState, UiUpdate},
+        view::{state_to_str, ViewCache},
+    },
 };
 
 use super::{
-    state::{start_refresh_thread, Playable, RssFeed, State},
-    Cmd,
+    cache, opml,
+    query::{Snapshot, Status},
+    state::{start_refresh_thread, Episode, Playable, RssFeed, State},
+    stats::{self, ExportFormat, Session, Stats},
+    store::{self, Store},
+    Cmd, Request, Response, UpdateArgs,
 };
 
 async fn start_observation(state: &State, feed_tx: Sender<Arc<RssFeed>>) {
@@ -37,48 +53,86 @@ async fn start_observation(state: &State, feed_tx: Sender<Arc<RssFeed>>) {
     }
 }
 
-pub async fn new(mut ui_rx: Receiver<UiUpdate>, ploop_tx: Sender<Cmd>) -> Sender<Cmd> {
-    let (tx, mut rx) = tokio::sync::mpsc::channel(32);
+/// The terminal interface, driven by key events from `updates`.
+struct Tui {
+    updates: Receiver<UiUpdate>,
+    state: UiState,
+    terminal: Terminal<CrosstermBackend<Stdout>>,
+}
+
+/// Starts the player task. `ui` attaches the terminal interface, without it
+/// the player runs headless. With `autosave` the state is written to disc at
+/// that interval while playing, it is always saved on shutdown. Progress is
+/// pushed to `peers` whenever it is recorded, what they did not get by
+/// shutdown is kept for the next start. Fails if the library cannot be opened.
+pub async fn new(
+    ui: Option<Receiver<UiUpdate>>,
+    autosave: Option<Duration>,
+    peers: Peers,
+    ploop_tx: Sender<Request>,
+) -> Result<Sender<Request>, String> {
+    let (tx, mut rx) = tokio::sync::mpsc::channel::<Request>(32);
     let ui_cmd_tx = tx.clone();
+    let episodes = Arc::new(Mutex::new(BTreeSet::new()));
+    let new_episodes = Arc::default();
+    let feed_tx = start_refresh_thread(episodes.clone(), Arc::clone(&new_episodes));
+    let mut player = Player::new(feed_tx.clone(), episodes.clone(), new_episodes, peers)
+        .map_err(|err| format!("failed to initialize player: {err}"))?;
     tokio::spawn(async move {
-        let mut ui_state = UiState::new(ui_cmd_tx);
-        let feed_tx = start_refresh_thread(ui_state.episodes.clone());
-        let mut player = match Player::new(feed_tx.clone()) {
-            Ok(player) => player,
-            Err(err) => {
-                logln!("failed to initialize player: {err}");
-                return;
-            }
-        };
         start_observation(&player.state, feed_tx.clone()).await;
 
         let mut bus_stream = player.playbin.bus().unwrap().stream();
         let mut ui_interval = time::interval(Duration::from_millis(100));
+        let mut position_interval = time::interval(Duration::from_secs(1));
+        let mut save_interval =
+            autosave.map(|period| time::interval_at(time::Instant::now() + period, period));
 
-        let stdout = std::io::stdout();
-        let backend = CrosstermBackend::new(stdout);
-        let mut terminal = match Terminal::new(backend) {
-            Err(err) => {
-                logln!("failed to initialize terminal: {err}");
-                return;
-            }
-            Ok(t) => t,
+        let mut tui = match ui {
+            Some(updates) => match Terminal::new(CrosstermBackend::new(std::io::stdout())) {
+                Ok(terminal) => Some(Tui {
+                    updates,
+                    state: UiState::new(ui_cmd_tx, episodes),
+                    terminal,
+                }),
+                Err(err) => {
+                    logln!("failed to initialize terminal: {err}");
+                    return;
+                }
+            },
+            None => None,
         };
+        let mut events = events::subscribe();
+        if tui.is_some() {
+            player.update_view(None);
+        }
 
-        let mut tick_count: u32 = 0;
         loop {
             select! {
-            Some(ui_update) = ui_rx.recv() => {
-                ui_state.update(ui_update, &mut player).await;
-                draw_ui(&mut terminal, &mut player, &mut ui_state);
+            Some(ui_update) = next_ui_update(&mut tui) => {
+                if let Some(tui) = tui.as_mut() {
+                    tui.state.update(ui_update, &player).await;
+                    draw_ui(&mut tui.terminal, &player, &mut tui.state);
+                }
             }
-            Some(cmd) = rx.recv() => {
+            Some(Request { cmd, reply }) = rx.recv() => {
                 if let Cmd::Shutdown = cmd {
                     logln!("quitting");
-                    ploop_tx.send(cmd).await.unwrap();
-                    run_cmd(Cmd::Shutdown, &mut player).await;
+                    ploop_tx.send(cmd.into()).await.unwrap();
+                    reply.send(log_err(run_cmd(Cmd::Shutdown, &mut player).await));
                     return
-                } else { run_cmd(cmd, &mut player).await};
+                } else { reply.send(log_err(run_cmd(cmd, &mut player).await)) };
+                if tui.is_some() {
+                    player.update_view(None);
+                }
+            }
+            event = next_event(&mut events) => {
+                if matches!(event, None | Some(Event::FeedRefreshed { .. })) {
+                    player.save_new_episodes();
+                }
+                // only the interface needs the view to be kept up to date
+                if tui.is_some() {
+                    player.update_view(event.as_ref());
+                }
             }
             msg = bus_stream.next() => {
                 if let Some(msg) = msg {
@@ -98,86 +152,244 @@ pub async fn new(mut ui_rx: Receiver<UiUpdate>, ploop_tx: Sender<Cmd>) -> Sender
                     }
                 }
             }
+            _ = position_interval.tick() => {
+                if player.playing {
+                    player.emit_position();
+                }
+            }
             _ = ui_interval.tick() => {
                 if player.duration.is_none() {
                     player.duration = player.playbin.query_duration();
                 }
-                draw_ui(&mut terminal, &mut player, &mut ui_state);
-                tick_count += 1;
-                // 100 ms * 1200 = 120 seconds
-                if tick_count >= 1200 {
-                    tick_count = 0;
-                    if player.playing {
-                        player.update_state();
-                        if let Err(err) = player.state.to_disc() {
-                            logln!("error while saving state: {err}");
-                        }
+                if let Some(tui) = tui.as_mut() {
+                    draw_ui(&mut tui.terminal, &player, &mut tui.state);
+                }
+            }
+            _ = tick(&mut save_interval) => {
+                if player.playing {
+                    player.update_state();
+                    if let Err(err) = player.save() {
+                        logln!("error while saving state: {err}");
                     }
                 }
-
             }
             }
         }
     });
-    tx
+    Ok(tx)
+}
+
+async fn next_ui_update(tui: &mut Option<Tui>) -> Option<UiUpdate> {
+    match tui {
+        Some(tui) => tui.updates.recv().await,
+        None => future::pending().await,
+    }
+}
+
+/// The next event, `None` if some were missed.
+async fn next_event(events: &mut broadcast::Receiver<Event>) -> Option<Event> {
+    match events.recv().await {
+        Ok(event) => Some(event),
+        Err(RecvError::Lagged(_)) => None,
+        // never happens, the sender is static
+        Err(RecvError::Closed) => future::pending().await,
+    }
 }
 
-async fn run_cmd(cmd: Cmd, player: &mut Player) {
+async fn tick(interval: &mut Option<Interval>) {
+    match interval {
+        Some(interval) => {
+            interval.tick().await;
+        }
+        None => future::pending().await,
+    }
+}
+
+async fn run_cmd(cmd: Cmd, player: &mut Player) -> Response {
     match cmd {
-        Cmd::Play => player.play(),
-        Cmd::Pause => player.pause(),
-        Cmd::PlayPause => player.play_pause(),
-        Cmd::Queue(uri) => player.queue(&uri),
-        Cmd::Seek(pos) => player.seek(pos),
-        Cmd::SeekRelative(delta) => player.seek_relative(delta),
+        Cmd::Status => to_response(&player.status()),
+        Cmd::ListQueue => to_response(&player.state.entries(&player.state.queue)),
+        Cmd::ListRecent => to_response(&player.state.entries(&player.state.recent)),
+        Cmd::ListFeeds => to_response(&player.state.feed_infos()),
+        Cmd::GetPlayable(uri) => match player.state.uris.get(&uri) {
+            Some(playable) => to_response(playable),
+            None => Err(format!("unknown uri: {uri}")),
+        },
+        Cmd::ListEpisodes => match player.episodes.lock() {
+            Ok(episodes) => to_response(&*episodes),
+            Err(err) => Err(format!("failed to lock episodes: {err}")),
+        },
+        Cmd::Snapshot => to_response(&Snapshot {
+            status: player.status(),
+            queue: player.state.queue.clone(),
+            recent: player.state.recent.clone(),
+            uris: player.state.uris.clone(),
+            feeds: player.state.feed_infos(),
+            stats: player.stats(),
+            new_episodes: player.new_episodes(),
+        }),
+        Cmd::Stats => to_response(&player.stats()),
+
+        Cmd::Play => player.play().map(|()| None),
+        Cmd::Pause => player.pause().map(|()| None),
+        Cmd::PlayPause => player.play_pause().map(|()| None),
+        Cmd::Queue(uri) => {
+            player.state.mark_seen(&uri);
+            player.queue(&uri);
+            Ok(None)
+        }
+        Cmd::PlayNow(uri) => {
+            player.update_state();
+            player.state.mark_seen(&uri);
+            player.state.queue_front(&uri);
+            player.next();
+            Ok(None)
+        }
+        Cmd::QueueEpisode(UpdateArgs(uri, playable)) => {
+            // keep listening where we left off if it was played before
+            let old = player.state.uris.get(&uri).cloned().unwrap_or_default();
+            player.state.insert_playable(
+                uri.clone(),
+                Playable {
+                    progress: old.progress,
+                    finished: old.finished,
+                    play_count: old.play_count,
+                    updated: Some(get_time()),
+                    ..playable
+                },
+            );
+            player.state.mark_seen(&uri);
+            player.queue(&uri);
+            Ok(None)
+        }
+        Cmd::MarkPlayed(uri) => {
+            if !player.state.uris.contains_key(&uri) {
+                // an episode of a feed that was never played
+                let playable = player
+                    .episode_playable(&uri)
+                    .ok_or_else(|| format!("unknown uri: {uri}"))?;
+                player.state.insert_playable(uri.clone(), playable);
+            }
+            player.state.mark_seen(&uri);
+            player.state.mark_played(&uri, get_time());
+            player.save().map(|()| None)
+        }
+        Cmd::MarkUnplayed(uri) => {
+            if !player.state.uris.contains_key(&uri) {
+                return Err(format!("unknown uri: {uri}"));
+            }
+            player.state.mark_seen(&uri);
+            player.state.mark_unplayed(&uri, get_time());
+            player.save().map(|()| None)
+        }
+        Cmd::MarkSeen(url) => {
+            let seen = player.state.mark_feed_seen(url.as_deref());
+            player.save()?;
+            to_response(&seen)
+        }
+        Cmd::Seek(pos) => player.seek(pos).map(|()| None),
+        Cmd::SeekRelative(delta) => player.seek_relative(delta).map(|()| None),
 
         Cmd::Subscribe(url) => {
             logln!("cmd to subscribe to {url}");
-            let new_feed = Arc::new(RssFeed {
-                uri: url.clone(),
-                channel: Arc::new(RwLock::new(None)),
-            });
-            if let Err(err) = player.feed_tx.send(new_feed.clone()).await {
-                logln!("failed send new feed: {err}");
-            }
-            if let Ok(mut feeds) = player.state.rss_feeds.lock() {
-                // TODO: better data structure for feeds?
-                if !feeds.iter().any(|x| x.uri == url) {
-                    feeds.push(new_feed);
+            player.subscribe(url).await?;
+            player.save().map(|()| None)
+        }
+        Cmd::Refresh(uri) => {
+            let feeds = match player.state.rss_feeds.lock() {
+                Ok(feeds) => feeds,
+                Err(err) => return Err(format!("failed to lock feeds: {err}")),
+            };
+            let mut refreshed = 0;
+            for feed in feeds.iter() {
+                if uri.is_none() || uri.as_ref() == Some(&feed.uri) {
+                    // one that is refreshing already counts as well
+                    feed.request_refresh();
+                    refreshed += 1;
                 }
             }
-            if let Err(err) = player.state.to_disc() {
-                logln!("{err}");
+            match uri {
+                Some(uri) if refreshed == 0 => Err(format!("not subscribed to {uri}")),
+                _ => Ok(Some(refreshed.into())),
+            }
+        }
+        Cmd::ImportOpml(xml) => {
+            let urls = opml::parse(&xml)?;
+            let mut added = 0;
+            for url in urls {
+                if player.subscribe(url).await? {
+                    added += 1;
+                }
             }
+            logln!("imported {added} new feeds from opml");
+            player.save().map(|()| Some(added.into()))
+        }
+        Cmd::ExportOpml => Ok(Some(opml::write(&player.state.feed_infos()).into())),
+        Cmd::ExportStats(ExportFormat::Csv) => Ok(Some(stats::to_csv(&player.sessions).into())),
+        Cmd::ExportStats(ExportFormat::Json) => Ok(Some(serde_json::json!({
+            "stats": player.stats(),
+            "sessions": player.sessions,
+        }))),
+        Cmd::Unsubscribe(url) => {
+            logln!("cmd to unsubscribe from {url}");
+            player.unsubscribe(&url, false)?;
+            player.save().map(|()| None)
+        }
+        Cmd::Purge(url) => {
+            logln!("cmd to unsubscribe from {url} and forget its episodes");
+            let purged = player.unsubscribe(&url, true)?;
+            player.save().map(|()| Some(purged.into()))
         }
         Cmd::Shutdown => {
             player.update_state();
+            player.end_session();
             if let Some(uri) = &player.current_uri {
                 player.state.queue_front(uri);
             }
             player.set_null();
-            if let Err(err) = player.state.to_disc() {
-                logln!("{err}");
+            let saved = player.save();
+            let unsent = player.peers.flush().await;
+            if let Err(err) = player.store.set_unsent(&unsent) {
+                logln!("failed to keep updates for peers: {err}");
             }
+            saved.map(|()| None)
         }
         Cmd::Next => {
             player.update_state();
-            player.next();
+            if player.next() {
+                Ok(None)
+            } else {
+                Err("queue is empty".into())
+            }
         }
         Cmd::Prev => {
             player.update_state();
-            player.prev();
+            if player.prev() {
+                Ok(None)
+            } else {
+                Err("no recent episodes".into())
+            }
         }
         Cmd::DeleteQueue(index) => {
             let uri = player.state.queue.remove(index);
             if let Some(uri) = uri.as_ref() {
                 player.state.push_recent(uri);
+                player.emit_queue_changed();
             }
-            log_delete(index, uri);
+            log_delete(index, uri)
         }
+        Cmd::Dequeue(uri) => match player.state.queue.iter().position(|queued| *queued == uri) {
+            Some(index) => {
+                player.state.queue.remove(index);
+                player.emit_queue_changed();
+                logln!("Dequeued {uri}");
+                Ok(None)
+            }
+            None => Err(format!("not queued: {uri}")),
+        },
         Cmd::DeleteRecent(index) => {
             let uri = player.state.recent.remove(index);
-            log_delete(index, uri);
+            log_delete(index, uri)
         }
         Cmd::Update(args) => {
             let uri = args.0;
@@ -187,16 +399,34 @@ async fn run_cmd(cmd: Cmd, player: &mut Player) {
                 args.1.length.unwrap_or_default(),
             );
             player.state.update_playable(uri, args.1);
+            Ok(None)
         }
     }
 }
 
-fn log_delete(index: usize, uri: Option<String>) {
+fn log_delete(index: usize, uri: Option<String>) -> Response {
     if let Some(uri) = uri {
         logln!("Deleting {index}: {uri}");
+        Ok(None)
     } else {
-        logln!("Deleting {index}: no such element");
+        Err(format!("Deleting {index}: no such element"))
+    }
+}
+
+fn to_response<T: Serialize>(value: &T) -> Response {
+    serde_json::to_value(value)
+        .map(Some)
+        .map_err(|err| format!("failed to serialize response: {err}"))
+}
+
+fn log_err(response: Response) -> Response {
+    if let Err(err) = &response {
+        logln!("{err}");
+        events::emit(Event::Error {
+            message: err.clone(),
+        });
     }
+    response
 }
 
 fn handle_message(player: &mut Player, msg: &gst::Message) -> bool {
@@ -211,6 +441,10 @@ fn handle_message(player: &mut Player, msg: &gst::Message) -> bool {
                 err.debug()
             );
 
+            events::emit(Event::Error {
+                message: err.error().to_string(),
+            });
+
             let err_str = err
                 .src()
                 .map(|src| src.path_string().to_string())
@@ -221,6 +455,7 @@ fn handle_message(player: &mut Player, msg: &gst::Message) -> bool {
 
             if err.error().to_string().contains("Connection terminated") {
                 logln!("pulse sink crashed :(");
+                player.end_session();
                 player.set_null();
                 player.playing = false;
 
@@ -231,8 +466,20 @@ fn handle_message(player: &mut Player, msg: &gst::Message) -> bool {
             logln!("End-Of-Stream reached.");
             if let Some(uri) = &player.current_uri {
                 logln!("finished {uri}");
-                player.state.reset_pos(uri);
+                // peers learn that it was listened to the end
+                if let Some(length) = player.duration.map(gst::ClockTime::seconds) {
+                    let finished = Playable {
+                        progress: Some(length),
+                        length: Some(length),
+                        updated: Some(get_time()),
+                        ..player.state.uris.get(uri).cloned().unwrap_or_default()
+                    };
+                    player.peers.push(&UpdateArgs(uri.clone(), finished));
+                }
+                player.state.finish(uri, get_time());
+                events::emit(Event::Finished { uri: uri.clone() });
             }
+            player.end_session();
             if !player.next() {
                 player.set_null();
             }
@@ -252,11 +499,20 @@ fn handle_message(player: &mut Player, msg: &gst::Message) -> bool {
                 if new_state == gst::State::Paused {
                     player.update_state();
                 };
+                if new_state == gst::State::Playing {
+                    player.start_session();
+                } else if old_state == gst::State::Playing {
+                    player.end_session();
+                }
 
                 logln!("Pipeline state: {:?} -> {:?}", old_state, new_state);
 
                 player.playing = new_state == gst::State::Playing;
                 player.play_state = new_state;
+                events::emit(Event::PlayState {
+                    state: format!("{new_state:?}"),
+                    playing: player.playing,
+                });
 
                 if player.playing {
                     let mut seeking = gst::query::Seeking::new(gst::Format::Time);
@@ -273,7 +529,9 @@ fn handle_message(player: &mut Player, msg: &gst::Message) -> bool {
                                     minutes,
                                     seconds
                                 );
-                                player.seek(pos);
+                                if let Err(err) = player.seek(pos) {
+                                    logln!("{err}");
+                                }
                             }
                         } else {
                             logln!("Seeking is DISABLED for this stream.");
@@ -293,10 +551,12 @@ fn handle_message(player: &mut Player, msg: &gst::Message) -> bool {
                         logln!("  Artist: {}", artist.get());
                     }
 
+                    let mut changed = false;
                     if let Some(title) = tags.get::<gst::tags::Title>() {
                         logln!("  Title: {}", title.get());
                         if state.title.is_none() {
                             state.title = Some(title.get().to_string());
+                            changed = true;
                         }
                     }
 
@@ -304,8 +564,13 @@ fn handle_message(player: &mut Player, msg: &gst::Message) -> bool {
                         logln!("  Album: {}", album.get());
                         if state.album.is_none() {
                             state.album = Some(album.get().to_string());
+                            changed = true;
                         }
                     }
+
+                    if changed {
+                        player.emit_track_changed();
+                    }
                 }
             }
         }
@@ -327,16 +592,36 @@ pub struct Player {
     seek_enabled: bool,
     pending_seek: Option<u64>,
     feed_tx: Sender<Arc<RssFeed>>,
+    episodes: Arc<Mutex<BTreeSet<Episode>>>,
+    peers: Peers,
+    store: Store,
+    /// Listening sessions, oldest first, and the one still going on.
+    sessions: Vec<Session>,
+    session: Option<Session>,
+    pub view: ViewCache,
 }
 
 impl Player {
-    fn new(feed_tx: Sender<Arc<RssFeed>>) -> Result<Self, Box<dyn Error>> {
+    fn new(
+        feed_tx: Sender<Arc<RssFeed>>,
+        episodes: Arc<Mutex<BTreeSet<Episode>>>,
+        new_episodes: Arc<Mutex<BTreeSet<String>>>,
+        peers: Peers,
+    ) -> Result<Self, Box<dyn Error>> {
         let playbin = gst::ElementFactory::make("playbin", Some("playbin"))?;
-        let state = State::from_disc()?;
+        let (mut store, mut state) = store::open()?;
+        // share what was loaded with the refresh thread
+        if let (Ok(mut shared), Ok(mut loaded)) = (new_episodes.lock(), state.new_episodes.lock()) {
+            shared.append(&mut loaded);
+        }
+        state.new_episodes = new_episodes;
+        let sessions = store.sessions()?;
+        peers.resume(store.unsent()?);
 
         Ok(Player {
             play_state: gst::State::Null,
             state,
+            store,
             pending_seek: None,
             playbin,
             playing: false,
@@ -344,49 +629,279 @@ impl Player {
             duration: gst::ClockTime::NONE,
             current_uri: None,
             feed_tx,
+            episodes,
+            peers,
+            sessions,
+            session: None,
+            view: ViewCache::default(),
         })
     }
 
+    /// Starts observing `url` unless it is subscribed to already, `false` then.
+    async fn subscribe(&mut self, url: String) -> Result<bool, String> {
+        let new_feed = match self.state.rss_feeds.lock() {
+            Ok(mut feeds) => {
+                // TODO: better data structure for feeds?
+                if feeds.iter().any(|x| x.uri == url) {
+                    return Ok(false);
+                }
+                let new_feed = Arc::new(RssFeed::new(url));
+                feeds.push(new_feed.clone());
+                new_feed
+            }
+            Err(err) => return Err(format!("failed to lock feeds: {err}")),
+        };
+        self.feed_tx
+            .send(new_feed)
+            .await
+            .map(|()| true)
+            .map_err(|err| format!("failed send new feed: {err}"))
+    }
+
+    /// Stops observing `url`. With `purge` the episodes it lists are dropped
+    /// as well, together with what is known about them; returns how many.
+    fn unsubscribe(&mut self, url: &str, purge: bool) -> Result<usize, String> {
+        let feed = match self.state.rss_feeds.lock() {
+            Ok(mut feeds) => match feeds.iter().position(|feed| feed.uri == url) {
+                Some(index) => feeds.remove(index),
+                None => return Err(format!("not subscribed to {url}")),
+            },
+            Err(err) => return Err(format!("failed to lock feeds: {err}")),
+        };
+        feed.stop();
+        cache::remove(&cache::dir(), url);
+        // the refresh thread checks whether the feed is stopped while holding
+        // this, so nothing of the feed is added after it is released
+        let mut episodes = match self.episodes.lock() {
+            Ok(episodes) => episodes,
+            Err(err) => return Err(format!("failed to lock episodes: {err}")),
+        };
+        if let Ok(mut new_episodes) = self.state.new_episodes.lock() {
+            for key in feed.keys() {
+                new_episodes.remove(&key);
+            }
+        }
+        events::emit(Event::Unsubscribed {
+            uri: url.to_string(),
+        });
+        if !purge {
+            return Ok(0);
+        }
+
+        let mut uris: HashSet<String> = match feed.channel.read() {
+            Ok(channel) => channel
+                .iter()
+                .flat_map(|channel| &channel.items)
+                .filter_map(|item| Some(item.enclosure()?.url.clone()))
+                .collect(),
+            Err(err) => return Err(format!("failed to lock channel: {err}")),
+        };
+        // keep what is playing or still queued
+        let in_use = self.current_uri.iter().chain(&self.state.queue);
+        let kept = in_use.filter(|uri| uris.remove(*uri)).count();
+        episodes.retain(|episode| {
+            let url = episode.item.enclosure().map(|e| e.url.as_str());
+            !url.is_some_and(|url| uris.contains(url))
+        });
+        self.state.uris.retain(|uri, _| !uris.contains(uri));
+        logln!(
+            "forgot {} episodes of {url}, kept {kept} playing or queued",
+            uris.len()
+        );
+        Ok(uris.len())
+    }
+
+    pub fn new_episodes(&self) -> BTreeSet<String> {
+        match self.state.new_episodes.lock() {
+            Ok(new_episodes) => new_episodes.clone(),
+            Err(_) => BTreeSet::new(),
+        }
+    }
+
+    /// Persists the new episodes right away, so that they are new after a
+    /// restart also if nothing else is saved before.
+    fn save_new_episodes(&mut self) {
+        self.state.prune_new_episodes();
+        if let Err(err) = self.store.save_new_episodes(&self.state) {
+            logln!("failed to save new episodes: {err}");
+        }
+    }
+
+    fn save(&mut self) -> Result<(), String> {
+        self.store
+            .save(&self.state)
+            .map_err(|err| format!("failed to save state: {err}"))
+    }
+
+    fn start_session(&mut self) {
+        let Some(uri) = &self.current_uri else {
+            return;
+        };
+        if self.session.is_some() {
+            return;
+        }
+        let now = get_time();
+        // a pending seek is where playback really starts
+        let position = self
+            .pending_seek
+            .or_else(|| self.query_position().map(gst::ClockTime::seconds));
+        self.session = Some(Session {
+            uri: uri.clone(),
+            feed: self.state.uris.get(uri).and_then(|p| p.album.clone()),
+            started: now,
+            stopped: now,
+            from: position,
+            to: position,
+        });
+    }
+
+    fn end_session(&mut self) {
+        let Some(mut session) = self.session.take() else {
+            return;
+        };
+        session.stopped = get_time();
+        // the position of another episode if it already changed
+        if self.current_uri.as_ref() == Some(&session.uri) {
+            if let Some(position) = self.query_position() {
+                session.to = Some(position.seconds());
+            }
+        }
+        if session.seconds() == 0 {
+            return;
+        }
+        if let Err(err) = self.store.record(&session) {
+            logln!("failed to record listening session: {err}");
+        }
+        self.sessions.push(session);
+    }
+
+    /// Title and podcast of the feed episode with the enclosure `uri`.
+    fn episode_playable(&self, uri: &str) -> Option<Playable> {
+        let episodes = self.episodes.lock().ok()?;
+        let episode = episodes
+            .iter()
+            .find(|episode| episode.item.enclosure().is_some_and(|e| e.url == uri))?;
+        Some(Playable {
+            title: episode.item.title.clone(),
+            album: Some(episode.channel_title.clone()),
+            ..Playable::default()
+        })
+    }
+
+    /// Updates the parts of the [`ViewCache`] that `event` changes, all of
+    /// them without one.
+    fn update_view(&mut self, event: Option<&Event>) {
+        let feeds = matches!(
+            event,
+            None | Some(
+                Event::FeedRefreshing { .. }
+                    | Event::FeedRefreshed { .. }
+                    | Event::NewEpisodes { .. }
+                    | Event::Unsubscribed { .. }
+            )
+        );
+        let stats = matches!(
+            event,
+            None | Some(
+                Event::Position { .. }
+                    | Event::PlayState { .. }
+                    | Event::Finished { .. }
+                    | Event::TrackChanged { .. }
+            )
+        );
+        if feeds {
+            self.view.feeds = self.state.feed_infos();
+            self.view.new_episodes = self.new_episodes();
+        }
+        if stats {
+            self.view.stats = self.stats();
+        }
+    }
+
+    /// Including the session going on right now.
+    pub fn stats(&self) -> Stats {
+        let current = self.session.clone().map(|session| Session {
+            stopped: get_time(),
+            ..session
+        });
+        Stats::new(self.sessions.iter().chain(&current), &self.state.uris)
+    }
+
     fn set_uri(&mut self, uri: &str) {
         self.current_uri = Some(uri.to_string());
         self.playbin.set_property("uri", uri);
+        self.emit_track_changed();
     }
 
     fn queue(&mut self, uri: &str) {
         self.state.queue(uri);
+        self.emit_queue_changed();
     }
 
-    fn play(&mut self) {
+    fn emit_track_changed(&self) {
+        if let Some(uri) = &self.current_uri {
+            let playable = self.state.uris.get(uri);
+            events::emit(Event::TrackChanged {
+                uri: uri.clone(),
+                title: playable.and_then(|p| p.title.clone()),
+                album: playable.and_then(|p| p.album.clone()),
+            });
+        }
+    }
+
+    fn emit_queue_changed(&self) {
+        events::emit(Event::QueueChanged {
+            len: self.state.queue.len(),
+        });
+    }
+
+    fn emit_position(&self) {
+        if let (Some(uri), Some(position)) = (&self.current_uri, self.query_position()) {
+            events::emit(Event::Position {
+                uri: uri.clone(),
+                position: position.seconds(),
+                duration: self.duration.map(gst::ClockTime::seconds),
+            });
+        }
+    }
+
+    fn play(&mut self) -> Result<(), String> {
         if self.playing {
-            return;
+            return Ok(());
         }
 
         if self.current_uri.is_none() {
             if let Some(new) = self.state.pop_queue() {
                 self.set_uri(&new);
+                self.emit_queue_changed();
             } else {
-                return;
+                return Err("nothing to play".into());
             }
         }
 
         let curi = self.current_uri.as_ref().unwrap();
         self.pending_seek = self.state.get_pos(curi);
-        if let Err(err) = self.playbin.set_state(gst::State::Playing) {
-            logln!("Unable to set the playbin to the `Playing` state: {err}");
-        }
+        self.playbin
+            .set_state(gst::State::Playing)
+            .map(|_| ())
+            .map_err(|err| format!("Unable to set the playbin to the `Playing` state: {err}"))
     }
 
     fn next(&mut self) -> bool {
         if let Some(next) = self.state.pop_queue() {
+            self.end_session();
             self.set_null();
             if let Some(uri) = &self.current_uri {
                 self.state.push_recent(uri);
             }
             self.duration = gst::ClockTime::NONE;
             self.set_uri(&next);
+            self.emit_queue_changed();
             if self.playing {
                 self.playing = false;
-                self.play();
+                if let Err(err) = self.play() {
+                    logln!("{err}");
+                }
             }
             return true;
         }
@@ -395,6 +910,7 @@ impl Player {
 
     fn prev(&mut self) -> bool {
         if let Some(next) = self.state.pop_recent() {
+            self.end_session();
             self.set_null();
             if let Some(uri) = &self.current_uri {
                 self.state.queue_front(uri);
@@ -402,21 +918,24 @@ impl Player {
             }
             self.duration = gst::ClockTime::NONE;
             self.set_uri(&next);
+            self.emit_queue_changed();
             if self.playing {
                 self.playing = false;
-                self.play();
+                if let Err(err) = self.play() {
+                    logln!("{err}");
+                }
             }
             return true;
         }
         false
     }
 
-    fn play_pause(&mut self) {
+    fn play_pause(&mut self) -> Result<(), String> {
         if self.playing {
-            self.pause();
+            self.pause()
         } else {
-            self.play();
-        };
+            self.play()
+        }
     }
 
     fn update_state(&mut self) {
@@ -429,33 +948,38 @@ impl Player {
 
                 let seconds = pos.seconds();
                 let t = get_time();
+                if let Some(session) = self.session.as_mut().filter(|s| &s.uri == uri) {
+                    session.to = Some(seconds);
+                }
                 if let Some(playable) = self.state.uris.get_mut(uri) {
                     playable.progress = Some(seconds);
                     playable.updated = Some(t);
                     playable.length = self.duration.map(gst::ClockTime::seconds);
                 } else {
                     let playable = Playable {
-                        title: None,
-                        album: None,
-                        source: None,
                         progress: Some(seconds),
                         length: self.duration.map(gst::ClockTime::seconds),
                         updated: Some(t),
+                        ..Playable::default()
                     };
                     self.state.insert_playable(uri.to_string(), playable);
                 };
+                if let Some(playable) = self.state.uris.get(uri) {
+                    self.peers
+                        .push(&UpdateArgs(uri.to_string(), playable.clone()));
+                }
             }
         }
     }
 
-    fn pause(&mut self) {
+    fn pause(&mut self) -> Result<(), String> {
         if self.playing {
             if let Err(err) = self.playbin.set_state(gst::State::Paused) {
-                logln!("Failed to set pipeline state to `Paused`: {err}");
-            } else {
-                self.update_state();
+                return Err(format!("Failed to set pipeline state to `Paused`: {err}"));
             }
+            self.update_state();
         }
+        Ok(())
     }
 
     fn set_null(&mut self) {
@@ -464,34 +988,50 @@ impl Player {
         }
     }
 
-    fn seek(&mut self, pos: u64) {
-        if let Err(err) = self.playbin.seek_simple(
-            gst::SeekFlags::FLUSH | gst::SeekFlags::KEY_UNIT,
-            gst::ClockTime::from_seconds(pos),
-        ) {
-            logln!("failed to seek: {err}");
-        }
+    fn seek(&mut self, pos: u64) -> Result<(), String> {
+        self.playbin
+            .seek_simple(
+                gst::SeekFlags::FLUSH | gst::SeekFlags::KEY_UNIT,
+                seek_target(pos, self.duration),
+            )
+            .map_err(|err| format!("failed to seek: {err}"))
     }
 
-    fn seek_relative(&mut self, delta: i64) {
+    fn seek_relative(&mut self, delta: i64) -> Result<(), String> {
         if let Some(current) = self.query_position() {
             let current = current.seconds();
             let new = if delta < 0 {
                 current.saturating_sub(delta.unsigned_abs())
             } else {
-                current
-                    .checked_add(delta.unsigned_abs())
-                    .unwrap_or(u64::MAX)
+                current.saturating_add(delta.unsigned_abs())
             };
-            self.seek(new);
+            self.seek(new)
         } else {
-            logln!("failed seek_relative (query_position())");
+            Err("failed seek_relative (query_position())".into())
         }
     }
 
     pub fn query_position(&self) -> Option<gst::ClockTime> {
         self.playbin.query_position::<gst::ClockTime>()
     }
+
+    pub fn status(&self) -> Status {
+        let playable = self
+            .current_uri
+            .as_ref()
+            .and_then(|uri| self.state.uris.get(uri));
+        Status {
+            state: state_to_str(self.play_state).to_string(),
+            playing: self.playing,
+            uri: self.current_uri.clone(),
+            title: playable.and_then(|p| p.title.clone()),
+            album: playable.and_then(|p| p.album.clone()),
+            position: self.query_position().map(gst::ClockTime::seconds),
+            duration: self.duration.map(gst::ClockTime::seconds),
+            queue_len: self.state.queue.len(),
+            warning: self.state.warning.clone(),
+        }
+    }
 }
 
 const fn clktime_to_hms(time: gst::ClockTime) -> (u64, u64, u64) {
@@ -500,3 +1040,28 @@ const fn clktime_to_hms(time: gst::ClockTime) -> (u64, u64, u64) {
     let hours = minutes / 60;
     (hours, minutes % 60, seconds % 60)
 }
+
+/// Seeks past the end land on the end, the longest `ClockTime` when it is not known yet.
+fn seek_target(pos: u64, duration: Option<gst::ClockTime>) -> gst::ClockTime {
+    let end = duration.map_or(u64::MAX / 1_000_000_000, gst::ClockTime::seconds);
+    gst::ClockTime::from_seconds(pos.min(end))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn seek_target_is_clamped() {
+        let duration = Some(gst::ClockTime::from_seconds(600));
+        assert_eq!(seek_target(30, duration), gst::ClockTime::from_seconds(30));
+        assert_eq!(
+            seek_target(u64::MAX, duration),
+            gst::ClockTime::from_seconds(600)
+        );
+        assert_eq!(
+            seek_target(u64::MAX, None).seconds(),
+            u64::MAX / 1_000_000_000
+        );
+    }
+}
@@ -0,0 +1,122 @@
+//! The last version of every feed that parsed, so its episodes are there right
+//! after startup, also offline. Kept in `feeds` in the
+//! [cache dir](crate::data::cache_dir), per feed the document as it was
+//! fetched and the [`Validators`] to ask the server whether it changed since.
+
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+use rss::Channel;
+use serde::{Deserialize, Serialize};
+
+use crate::{data, logln};
+
+use super::fetch::Validators;
+
+pub fn dir() -> PathBuf {
+    data::cache_dir().join("feeds")
+}
+
+#[derive(Serialize, Deserialize)]
+struct Meta {
+    uri: String,
+    #[serde(flatten)]
+    validators: Validators,
+}
+
+/// The feed as cached in `dir`, if it is there and still parses. The
+/// metadata names the feed the document belongs to, another feed whose uri
+/// hashes the same is a miss.
+pub fn load(dir: &Path, uri: &str) -> Option<(Channel, Validators)> {
+    let (document, meta) = paths(dir, uri);
+    let meta = serde_json::from_slice::<Meta>(&fs::read(meta).ok()?).ok()?;
+    if meta.uri != uri {
+        return None;
+    }
+    let content = fs::read(document).ok()?;
+    match Channel::read_from(&content[..]) {
+        Ok(channel) => Some((channel, meta.validators)),
+        Err(err) => {
+            logln!("ignoring cached {uri}: {err}");
+            None
+        }
+    }
+}
+
+pub fn store(dir: &Path, uri: &str, content: &[u8], validators: &Validators) -> io::Result<()> {
+    let (document, meta) = paths(dir, uri);
+    let meta_content = serde_json::to_vec(&Meta {
+        uri: uri.to_string(),
+        validators: validators.clone(),
+    })?;
+    data::write_atomic(&document, content)?;
+    data::write_atomic(&meta, &meta_content)
+}
+
+pub fn remove(dir: &Path, uri: &str) {
+    let (document, meta) = paths(dir, uri);
+    for path in [document, meta] {
+        if let Err(err) = fs::remove_file(&path) {
+            if err.kind() != io::ErrorKind::NotFound {
+                logln!("failed to remove {}: {err}", path.display());
+            }
+        }
+    }
+}
+
+/// Files named after a hash of `uri`, urls make poor file names.
+fn paths(dir: &Path, uri: &str) -> (PathBuf, PathBuf) {
+    // FNV-1a, unlike `DefaultHasher` it stays the same across Rust versions
+    let hash = uri.bytes().fold(0xcbf2_9ce4_8422_2325_u64, |hash, byte| {
+        (hash ^ u64::from(byte)).wrapping_mul(0x0100_0000_01b3)
+    });
+    (
+        dir.join(format!("{hash:016x}.xml")),
+        dir.join(format!("{hash:016x}.json")),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        let dir = std::env::temp_dir().join(format!("podaemon-cache-{}", std::process::id()));
+        let uri = "https://a.example/feed?format=rss";
+        assert!(load(&dir, uri).is_none());
+
+        let xml = r#"<rss version="2.0"><channel><title>Show</title>
+            <item><title>One</title></item></channel></rss>"#;
+        let validators = Validators {
+            etag: Some("\"v1\"".into()),
+            last_modified: None,
+        };
+        store(&dir, uri, xml.as_bytes(), &validators).unwrap();
+        let (channel, cached) = load(&dir, uri).unwrap();
+        assert_eq!(channel.title, "Show");
+        assert_eq!(channel.items.len(), 1);
+        assert_eq!(cached, validators);
+        assert!(load(&dir, "https://b.example/feed").is_none());
+
+        // the files of another feed with the same hash
+        let other = serde_json::to_vec(&Meta {
+            uri: "https://b.example/feed".into(),
+            validators: Validators::default(),
+        })
+        .unwrap();
+        fs::write(paths(&dir, uri).1, other).unwrap();
+        assert!(load(&dir, uri).is_none());
+        store(&dir, uri, xml.as_bytes(), &validators).unwrap();
+
+        // a broken document is a miss rather than an empty feed
+        fs::write(paths(&dir, uri).0, "<rss><channel>").unwrap();
+        assert!(load(&dir, uri).is_none());
+
+        remove(&dir, uri);
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 0);
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -1,11 +1,19 @@
 use crate::logln;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
 use strum_macros::EnumString;
 use strum_macros::{AsStaticStr, Display};
+use tokio::sync::oneshot;
 
-use super::state::Playable;
+use super::{state::Playable, stats::ExportFormat};
 
-#[derive(Debug, EnumString, AsStaticStr, Display, PartialEq, Eq)]
+/// Commands understood by the player. On the wire they are either written as
+/// `variant` / `variant(arg)` (see [`parse_cmd`]) or as JSON, where the
+/// variant is the `method` and its argument the `params`:
+/// `{"method": "seek_relative", "params": -10}`.
+#[derive(Debug, EnumString, AsStaticStr, Display, PartialEq, Eq, Serialize, Deserialize)]
 #[strum(serialize_all = "snake_case")]
+#[serde(tag = "method", content = "params", rename_all = "snake_case")]
 pub enum Cmd {
     Next,
     Prev,
@@ -13,95 +21,301 @@ pub enum Cmd {
     Pause,
     PlayPause,
     Queue(String),
+    /// Plays the uri right away, what was playing goes to the recent list.
+    PlayNow(String),
     Shutdown,
     Seek(u64),
     SeekRelative(i64),
     DeleteQueue(usize),
+    /// Takes the uri out of the queue without it going to the recent list.
+    Dequeue(String),
     DeleteRecent(usize),
     Subscribe(String),
+    Unsubscribe(String),
+    /// Refreshes one feed, or all without a uri, right away. Answers how
+    /// many feeds are being refreshed.
+    Refresh(Option<String>),
+    /// Unsubscribes and forgets the episodes of the feed and their progress,
+    /// answers how many episodes were forgotten.
+    Purge(String),
+    /// Subscribes to the feeds of an OPML document, answers how many were new.
+    ImportOpml(String),
+    /// Answers the subscriptions as an OPML document.
+    ExportOpml,
     Update(UpdateArgs),
+    Status,
+    ListQueue,
+    ListRecent,
+    ListFeeds,
+    GetPlayable(String),
+    ListEpisodes,
+    Snapshot,
+    /// Queues an episode of a feed, remembering its title and podcast.
+    QueueEpisode(UpdateArgs),
+    MarkPlayed(String),
+    MarkUnplayed(String),
+    /// Marks the new episodes of one feed, or of all without a uri, as seen.
+    /// Answers how many there were.
+    MarkSeen(Option<String>),
+    /// Listening statistics, see [`Stats`](super::stats::Stats).
+    Stats,
+    /// Answers the listening sessions, as CSV text or as JSON together with
+    /// the statistics.
+    ExportStats(ExportFormat),
 }
 
-#[derive(Eq, PartialEq, Debug)]
-pub struct UpdateArgs(pub String, pub Playable);
-impl Default for UpdateArgs {
-    fn default() -> Self {
-        UpdateArgs(
-            String::default(),
-            Playable {
-                title: None,
-                album: None,
-                source: None,
-                progress: None,
-                updated: None,
-                length: None,
+/// Outcome of executing a [`Cmd`], reported back to whoever issued it.
+/// Queries carry their result as JSON.
+pub type Response = Result<Option<Value>, String>;
+
+/// A [`Cmd`] together with the channel on which its outcome is reported.
+#[derive(Debug)]
+pub struct Request {
+    pub cmd: Cmd,
+    pub reply: Reply,
+}
+
+impl Request {
+    pub fn new(cmd: Cmd) -> (Self, oneshot::Receiver<Response>) {
+        let (tx, rx) = oneshot::channel();
+        (
+            Request {
+                cmd,
+                reply: Reply(Some(tx)),
             },
+            rx,
         )
     }
 }
 
+impl From<Cmd> for Request {
+    fn from(cmd: Cmd) -> Self {
+        Request {
+            cmd,
+            reply: Reply(None),
+        }
+    }
+}
+
+/// Reply half of a [`Request`], commands sent without one are fire and forget.
+#[derive(Debug)]
+pub struct Reply(Option<oneshot::Sender<Response>>);
+
+impl Reply {
+    pub fn send(self, response: Response) {
+        if let Some(tx) = self.0 {
+            // the requester may have hung up, nothing to do about it then
+            let _ = tx.send(response);
+        }
+    }
+}
+
+#[derive(Eq, PartialEq, Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(from = "UpdateParams", into = "UpdateParams")]
+pub struct UpdateArgs(pub String, pub Playable);
+
+/// Named form of [`UpdateArgs`] used in JSON: `{"uri": .., "progress": .., ..}`.
+#[derive(Serialize, Deserialize)]
+struct UpdateParams {
+    uri: String,
+    #[serde(flatten)]
+    playable: Playable,
+}
+
+impl From<UpdateParams> for UpdateArgs {
+    fn from(UpdateParams { uri, playable }: UpdateParams) -> Self {
+        UpdateArgs(uri, playable)
+    }
+}
+
+impl From<UpdateArgs> for UpdateParams {
+    fn from(UpdateArgs(uri, playable): UpdateArgs) -> Self {
+        UpdateParams { uri, playable }
+    }
+}
+
 impl UpdateArgs {
-    /*     pub fn to_cmd_string(self) -> String { */
-    /* let Self( */
-    /* uri, */
-    /* Playable { */
-    /* title: _, */
-    /* album: _, */
-    /* source: _, */
-    /* progress: p, */
-    /* updated: t, */
-    /* length: l, */
-    /* }, */
-    /* ) = self; */
-    /* // format!("update({uri},{t:?},{p:?})") */
-    /* todo!("not implemented"); */
-    /* } */
+    /// The `update(uri,progress,length,time)` form read by [`parse_cmd`],
+    /// `None` without a progress or time. An unknown length is sent as 0.
+    pub fn to_cmd_string(&self) -> Option<String> {
+        let Self(
+            uri,
+            Playable {
+                progress,
+                length,
+                updated,
+                ..
+            },
+        ) = self;
+        Some(format!(
+            "update({uri},{},{},{})",
+            (*progress)?,
+            length.unwrap_or_default(),
+            (*updated)?
+        ))
+    }
 
     pub fn parse(raw: &str) -> Option<Self> {
-        let mut rs = raw.split(',');
-        let uri = rs.next()?;
-        let progress = rs.next().and_then(|s| s.parse::<u64>().ok())?;
-        let length = rs.next().and_then(|s| s.parse::<u64>().ok())?;
+        // split from the right, the uri itself may contain commas
+        let mut rs = raw.rsplitn(4, ',');
         let time = rs.next().and_then(|s| s.parse::<u64>().ok())?;
+        let length = rs.next().and_then(|s| s.parse::<u64>().ok())?;
+        let progress = rs.next().and_then(|s| s.parse::<u64>().ok())?;
+        let uri = rs.next()?;
         Some(UpdateArgs(
             uri.to_string(),
             Playable {
-                title: None,
-                album: None,
-                source: None,
                 progress: Some(progress),
-                length: Some(length),
+                length: (length > 0).then_some(length),
                 updated: Some(time),
+                ..Playable::default()
             },
         ))
     }
 }
 
 pub fn parse_cmd(buf: &str) -> Option<Cmd> {
-    if let cmd @ Ok(_) = buf.trim_end().parse() {
-        cmd.ok()
-    } else if let cmd @ Some(_) = parse_cmd_arg(buf) {
-        cmd
-    } else {
-        logln!("parse failed: {buf}");
-        None
+    match try_parse_cmd(buf) {
+        Ok(cmd) => Some(cmd),
+        Err(err) => {
+            logln!("parse failed: {err}");
+            None
+        }
+    }
+}
+
+pub fn try_parse_cmd(buf: &str) -> Result<Cmd, String> {
+    let buf = buf.trim_end();
+    match buf.parse() {
+        // strum makes up a default for the argument that was left out
+        Ok(cmd) if needs_arg(&cmd) => Err(format!("missing argument for {buf}")),
+        Ok(cmd) => Ok(cmd),
+        Err(_) => parse_cmd_arg(buf),
+    }
+}
+
+/// Whether `cmd` can only be written as `variant(arg)`. Without one,
+/// `refresh` and `mark_seen` apply to all feeds.
+fn needs_arg(cmd: &Cmd) -> bool {
+    match cmd {
+        Cmd::Next
+        | Cmd::Prev
+        | Cmd::Play
+        | Cmd::Pause
+        | Cmd::PlayPause
+        | Cmd::Shutdown
+        | Cmd::ExportOpml
+        | Cmd::Status
+        | Cmd::ListQueue
+        | Cmd::ListRecent
+        | Cmd::ListFeeds
+        | Cmd::ListEpisodes
+        | Cmd::Snapshot
+        | Cmd::Stats
+        | Cmd::Refresh(_)
+        | Cmd::MarkSeen(_) => false,
+        Cmd::Queue(_)
+        | Cmd::PlayNow(_)
+        | Cmd::Seek(_)
+        | Cmd::SeekRelative(_)
+        | Cmd::DeleteQueue(_)
+        | Cmd::Dequeue(_)
+        | Cmd::DeleteRecent(_)
+        | Cmd::Subscribe(_)
+        | Cmd::Unsubscribe(_)
+        | Cmd::Purge(_)
+        | Cmd::ImportOpml(_)
+        | Cmd::Update(_)
+        | Cmd::GetPlayable(_)
+        | Cmd::QueueEpisode(_)
+        | Cmd::MarkPlayed(_)
+        | Cmd::MarkUnplayed(_)
+        | Cmd::ExportStats(_) => true,
     }
 }
 
-fn parse_cmd_arg(buf: &str) -> Option<Cmd> {
-    if let Some((variant, Some(arg))) = buf
+fn parse_cmd_arg(buf: &str) -> Result<Cmd, String> {
+    let Some((variant, Some(arg))) = buf
         .split_once('(')
         .map(|(variant, s)| (variant, s.strip_suffix(')')))
-    {
-        match variant {
-            // TODO: make more extensible somehow
-            "queue" => return Some(Cmd::Queue(arg.into())),
-            "seek" => return arg.parse().ok().map(Cmd::Seek),
-            "seek_relative" => return arg.parse().ok().map(Cmd::SeekRelative),
-            "subscribe" => return arg.parse().ok().map(Cmd::Subscribe),
-            "update" => return UpdateArgs::parse(arg).map(Cmd::Update),
-            _ => {}
+    else {
+        return Err(format!("unknown command: {buf}"));
+    };
+
+    let invalid = || format!("invalid argument for {variant}: {arg}");
+    match variant {
+        // TODO: make more extensible somehow
+        "queue" => Ok(Cmd::Queue(arg.into())),
+        "play_now" => Ok(Cmd::PlayNow(arg.into())),
+        "seek" => arg.parse().map(Cmd::Seek).map_err(|_| invalid()),
+        "seek_relative" => arg.parse().map(Cmd::SeekRelative).map_err(|_| invalid()),
+        "subscribe" => Ok(Cmd::Subscribe(arg.into())),
+        "unsubscribe" => Ok(Cmd::Unsubscribe(arg.into())),
+        "refresh" => Ok(Cmd::Refresh(Some(arg.into()))),
+        "purge" => Ok(Cmd::Purge(arg.into())),
+        "import_opml" => Ok(Cmd::ImportOpml(arg.into())),
+        "export_stats" => match arg {
+            "json" => Ok(Cmd::ExportStats(ExportFormat::Json)),
+            "csv" => Ok(Cmd::ExportStats(ExportFormat::Csv)),
+            _ => Err(invalid()),
+        },
+        "get_playable" => Ok(Cmd::GetPlayable(arg.into())),
+        "mark_played" => Ok(Cmd::MarkPlayed(arg.into())),
+        "mark_unplayed" => Ok(Cmd::MarkUnplayed(arg.into())),
+        "mark_seen" => Ok(Cmd::MarkSeen(Some(arg.into()))),
+        "delete_queue" => arg.parse().map(Cmd::DeleteQueue).map_err(|_| invalid()),
+        "dequeue" => Ok(Cmd::Dequeue(arg.into())),
+        "delete_recent" => arg.parse().map(Cmd::DeleteRecent).map_err(|_| invalid()),
+        "update" => UpdateArgs::parse(arg).map(Cmd::Update).ok_or_else(invalid),
+        _ => Err(format!("unknown command: {variant}")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn update_round_trip() {
+        let mut args = UpdateArgs::default();
+        args.0 = "https://example.com/ep.mp3?a=1,2".into();
+        args.1.progress = Some(90);
+        args.1.updated = Some(1_700_000_000);
+
+        let line = args.to_cmd_string().unwrap();
+        assert_eq!(
+            line,
+            "update(https://example.com/ep.mp3?a=1,2,90,0,1700000000)"
+        );
+        assert_eq!(try_parse_cmd(&line), Ok(Cmd::Update(args.clone())));
+
+        args.1.length = Some(3600);
+        let line = args.to_cmd_string().unwrap();
+        assert_eq!(try_parse_cmd(&line), Ok(Cmd::Update(args.clone())));
+
+        args.1.updated = None;
+        assert_eq!(args.to_cmd_string(), None);
+    }
+
+    #[test]
+    fn parse_refresh() {
+        assert_eq!(try_parse_cmd("refresh"), Ok(Cmd::Refresh(None)));
+        assert_eq!(
+            try_parse_cmd("refresh(https://a.example/feed)"),
+            Ok(Cmd::Refresh(Some("https://a.example/feed".into())))
+        );
+        assert_eq!(try_parse_cmd("mark_seen"), Ok(Cmd::MarkSeen(None)));
+    }
+
+    #[test]
+    fn missing_argument() {
+        for name in ["seek", "delete_queue", "queue", "play_now", "subscribe"] {
+            assert_eq!(
+                try_parse_cmd(name),
+                Err(format!("missing argument for {name}"))
+            );
         }
+        assert_eq!(try_parse_cmd("seek(30)"), Ok(Cmd::Seek(30)));
+        assert_eq!(try_parse_cmd("play_pause"), Ok(Cmd::PlayPause));
     }
-    None
 }
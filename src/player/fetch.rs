@@ -0,0 +1,314 @@
+//! Fetching feeds without hammering their publishers. Requests are conditional
+//! on the `ETag` and `Last-Modified` of the previous response, an unchanged
+//! feed then only costs a `304 Not Modified`.
+//!
+//! A feed is fetched again after `FEED_INTERVAL` seconds, one hour by default,
+//! or later if the channel asks for it with `<ttl>` or `sy:updatePeriod`, but
+//! at least once a day. Its `skipHours` and `skipDays` are skipped. Failing
+//! feeds are retried after 5 minutes, doubling up to a day. Requests identify
+//! as `podaemon/<version>` unless `FEED_USER_AGENT` says otherwise.
+
+use std::{env, fmt, time::Duration};
+
+use chrono::{DateTime, Datelike, Timelike, Utc, Weekday};
+use reqwest::{
+    header::{HeaderMap, ETAG, IF_MODIFIED_SINCE, IF_NONE_MATCH, LAST_MODIFIED, RETRY_AFTER},
+    Client, StatusCode,
+};
+use rss::{extension::syndication::UpdatePeriod, Channel};
+use serde::{Deserialize, Serialize};
+
+use crate::logln;
+
+const DEFAULT_INTERVAL: u64 = 60 * 60;
+const MAX_INTERVAL: u64 = 24 * 60 * 60;
+const FIRST_RETRY: u64 = 5 * 60;
+
+pub fn client() -> reqwest::Result<Client> {
+    let user_agent = env::var("FEED_USER_AGENT")
+        .unwrap_or_else(|_| format!("podaemon/{}", env!("CARGO_PKG_VERSION")));
+    Client::builder().user_agent(user_agent).build()
+}
+
+/// What the server told us to send back to learn whether the feed changed.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Validators {
+    pub etag: Option<String>,
+    pub last_modified: Option<String>,
+}
+
+#[derive(Debug)]
+pub enum Fetched {
+    /// The parsed channel and the document it was parsed from.
+    Modified(Box<Channel>, Vec<u8>),
+    NotModified,
+}
+
+#[derive(Debug)]
+pub struct FetchError {
+    message: String,
+    /// How long the server asked us to wait with `Retry-After`.
+    retry_after: Option<Duration>,
+}
+
+impl fmt::Display for FetchError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+impl From<reqwest::Error> for FetchError {
+    fn from(err: reqwest::Error) -> Self {
+        FetchError {
+            message: err.to_string(),
+            retry_after: None,
+        }
+    }
+}
+
+/// Fetches `uri` unless it is unchanged since `validators` were received,
+/// they are replaced by those of a new response.
+pub async fn fetch(
+    client: &Client,
+    uri: &str,
+    validators: &mut Validators,
+) -> Result<Fetched, FetchError> {
+    let mut request = client.get(uri);
+    if let Some(etag) = &validators.etag {
+        request = request.header(IF_NONE_MATCH, etag);
+    }
+    if let Some(last_modified) = &validators.last_modified {
+        request = request.header(IF_MODIFIED_SINCE, last_modified);
+    }
+    let response = request.send().await?;
+    let status = response.status();
+    if status == StatusCode::NOT_MODIFIED {
+        return Ok(Fetched::NotModified);
+    }
+    if !status.is_success() {
+        return Err(FetchError {
+            message: status.to_string(),
+            retry_after: retry_after(response.headers()),
+        });
+    }
+
+    let header = |name| {
+        let value = response.headers().get(name)?.to_str().ok()?;
+        Some(value.to_string())
+    };
+    let new_validators = Validators {
+        etag: header(ETAG),
+        last_modified: header(LAST_MODIFIED),
+    };
+    let content = response.bytes().await?;
+    let channel = Channel::read_from(&content[..]).map_err(|err| FetchError {
+        message: format!("invalid feed: {err}"),
+        retry_after: None,
+    })?;
+    *validators = new_validators;
+    Ok(Fetched::Modified(Box::new(channel), content.to_vec()))
+}
+
+/// In seconds or as an HTTP date.
+fn retry_after(headers: &HeaderMap) -> Option<Duration> {
+    let value = headers.get(RETRY_AFTER)?.to_str().ok()?.trim();
+    if let Ok(seconds) = value.parse() {
+        return Some(Duration::from_secs(seconds));
+    }
+    let date = DateTime::parse_from_rfc2822(value).ok()?;
+    (date.with_timezone(&Utc) - Utc::now()).to_std().ok()
+}
+
+/// When a feed is due again.
+#[derive(Debug)]
+pub struct Schedule {
+    interval: u64,
+    failures: u32,
+}
+
+impl Schedule {
+    pub fn from_env() -> Self {
+        let interval = match env::var("FEED_INTERVAL") {
+            Ok(secs) => secs.parse().unwrap_or_else(|_| {
+                logln!("invalid FEED_INTERVAL: {secs}");
+                DEFAULT_INTERVAL
+            }),
+            Err(_) => DEFAULT_INTERVAL,
+        };
+        Schedule {
+            interval: interval.max(1),
+            failures: 0,
+        }
+    }
+
+    /// The delay until the next refresh of `channel`, fetched at `now`.
+    pub fn succeeded(&mut self, channel: Option<&Channel>, now: DateTime<Utc>) -> Duration {
+        self.failures = 0;
+        let Some(channel) = channel else {
+            return Duration::from_secs(self.interval);
+        };
+        let interval = self
+            .interval
+            .max(requested_interval(channel))
+            .min(MAX_INTERVAL);
+        let due = now.timestamp().max(0) as u64 + interval;
+        Duration::from_secs(skip(channel, due) - now.timestamp().max(0) as u64)
+    }
+
+    pub fn failed(&mut self, err: &FetchError) -> Duration {
+        let backoff = FIRST_RETRY
+            .saturating_mul(1 << self.failures.min(16))
+            .min(MAX_INTERVAL);
+        self.failures += 1;
+        let backoff = Duration::from_secs(backoff);
+        // a server asking for more than a day is not waited for any longer
+        let wait = err.retry_after.map_or(backoff, |wait| wait.max(backoff));
+        wait.min(Duration::from_secs(MAX_INTERVAL))
+    }
+}
+
+/// What `<ttl>` and `sy:updatePeriod` ask for, in seconds.
+fn requested_interval(channel: &Channel) -> u64 {
+    let ttl = channel
+        .ttl()
+        .and_then(|ttl| ttl.trim().parse::<u64>().ok())
+        .map_or(0, |minutes| minutes * 60);
+    let syndication = channel.syndication_ext().map_or(0, |sy| {
+        let period = match sy.period() {
+            UpdatePeriod::Hourly => 60 * 60,
+            UpdatePeriod::Daily => 24 * 60 * 60,
+            UpdatePeriod::Weekly => 7 * 24 * 60 * 60,
+            UpdatePeriod::Monthly => 30 * 24 * 60 * 60,
+            UpdatePeriod::Yearly => 365 * 24 * 60 * 60,
+        };
+        period / u64::from(sy.frequency().max(1))
+    });
+    ttl.max(syndication)
+}
+
+/// The first full hour from `due` on, in unix seconds, that is not in the
+/// `skipHours` (GMT) or `skipDays` of `channel`.
+fn skip(channel: &Channel, due: u64) -> u64 {
+    let hours: Vec<u32> = channel
+        .skip_hours()
+        .iter()
+        .filter_map(|hour| hour.trim().parse().ok())
+        .collect();
+    let days: Vec<Weekday> = channel
+        .skip_days()
+        .iter()
+        .filter_map(|day| day.trim().parse().ok())
+        .collect();
+    let skipped = |time: u64| {
+        let Some(time) = DateTime::from_timestamp(time as i64, 0) else {
+            return false;
+        };
+        hours.contains(&time.hour()) || days.contains(&time.weekday())
+    };
+    let mut time = due;
+    // a week covers every hour, skipping all of them is ignored
+    for _ in 0..7 * 24 {
+        if !skipped(time) {
+            return time;
+        }
+        time = (time / 3600 + 1) * 3600;
+    }
+    due
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{response::IntoResponse, routing::get, Router, Server};
+
+    use super::*;
+
+    fn channel(extra: &str) -> Channel {
+        let xml = format!(
+            r#"<rss version="2.0" xmlns:sy="http://purl.org/rss/1.0/modules/syndication/">
+                 <channel><title>t</title><link>l</link><description>d</description>{extra}</channel>
+               </rss>"#
+        );
+        Channel::read_from(xml.as_bytes()).unwrap()
+    }
+
+    #[test]
+    fn schedule() {
+        // Friday 2024-02-02 10:00 UTC
+        let now = DateTime::from_timestamp(1_706_868_000, 0).unwrap();
+        let hours = |delay: Duration| delay.as_secs() / 3600;
+        let mut schedule = Schedule {
+            interval: DEFAULT_INTERVAL,
+            failures: 0,
+        };
+        assert_eq!(hours(schedule.succeeded(Some(&channel("")), now)), 1);
+        let ttl = channel("<ttl>180</ttl>");
+        assert_eq!(hours(schedule.succeeded(Some(&ttl), now)), 3);
+        let sy = channel(
+            "<sy:updatePeriod>daily</sy:updatePeriod><sy:updateFrequency>2</sy:updateFrequency>",
+        );
+        assert_eq!(hours(schedule.succeeded(Some(&sy), now)), 12);
+        let yearly = channel("<sy:updatePeriod>yearly</sy:updatePeriod>");
+        assert_eq!(hours(schedule.succeeded(Some(&yearly), now)), 24);
+
+        let skip_hours = channel("<skipHours><hour>11</hour><hour>12</hour></skipHours>");
+        assert_eq!(hours(schedule.succeeded(Some(&skip_hours), now)), 3);
+        let weekend =
+            channel("<ttl>1440</ttl><skipDays><day>Saturday</day><day>Sunday</day></skipDays>");
+        assert_eq!(hours(schedule.succeeded(Some(&weekend), now)), 62);
+
+        let err = FetchError {
+            message: "503".into(),
+            retry_after: None,
+        };
+        let minutes: Vec<u64> = (0..4)
+            .map(|_| schedule.failed(&err).as_secs() / 60)
+            .collect();
+        assert_eq!(minutes, [5, 10, 20, 40]);
+        let err = FetchError {
+            message: "429".into(),
+            retry_after: Some(Duration::from_secs(7200)),
+        };
+        assert_eq!(schedule.failed(&err).as_secs(), 7200);
+        let err = FetchError {
+            message: "429".into(),
+            retry_after: Some(Duration::from_secs(u64::MAX)),
+        };
+        assert_eq!(schedule.failed(&err).as_secs(), MAX_INTERVAL);
+        schedule.succeeded(None, now);
+        assert_eq!(schedule.failures, 0);
+    }
+
+    #[tokio::test]
+    async fn conditional_get() {
+        let feed = |headers: HeaderMap| async move {
+            if headers.get("if-none-match").map(|v| v.as_bytes()) == Some(b"\"v1\"") {
+                return StatusCode::NOT_MODIFIED.into_response();
+            }
+            let xml = r#"<rss version="2.0"><channel><title>Show</title></channel></rss>"#;
+            ([("etag", "\"v1\"")], xml).into_response()
+        };
+        let app = Router::new().route("/feed", get(feed));
+        let server = Server::bind(&"127.0.0.1:0".parse().unwrap()).serve(app.into_make_service());
+        let url = format!("http://{}/feed", server.local_addr());
+        tokio::spawn(server);
+
+        let client = client().unwrap();
+        let mut validators = Validators::default();
+        let Fetched::Modified(channel, _) = fetch(&client, &url, &mut validators).await.unwrap()
+        else {
+            panic!("expected the feed");
+        };
+        assert_eq!(channel.title, "Show");
+        assert_eq!(validators.etag.as_deref(), Some("\"v1\""));
+        assert!(matches!(
+            fetch(&client, &url, &mut validators).await.unwrap(),
+            Fetched::NotModified
+        ));
+
+        let missing = format!("{url}/missing");
+        let err = fetch(&client, &missing, &mut Validators::default())
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("404"), "{err}");
+    }
+}
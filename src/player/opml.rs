@@ -0,0 +1,112 @@
+//! OPML 2.0 lists of subscriptions, as written and read by most podcast apps.
+
+use quick_xml::{escape::escape, events::Event, Reader, XmlVersion};
+
+use super::query::FeedInfo;
+
+/// The `xmlUrl` of every `outline`, also of those nested in categories, in
+/// document order and without duplicates.
+pub fn parse(xml: &str) -> Result<Vec<String>, String> {
+    let mut reader = Reader::from_str(xml);
+    let mut urls: Vec<String> = Vec::new();
+    loop {
+        let element = match reader.read_event() {
+            Ok(Event::Start(element) | Event::Empty(element)) => element,
+            Ok(Event::Eof) => break,
+            Ok(_) => continue,
+            Err(err) => {
+                let pos = reader.error_position();
+                return Err(format!("invalid opml at {pos}: {err}"));
+            }
+        };
+        if element.local_name().as_ref() != b"outline" {
+            continue;
+        }
+        for attr in element.attributes() {
+            let attr = attr.map_err(|err| format!("invalid opml: {err}"))?;
+            if attr.key.local_name().as_ref() != b"xmlUrl" {
+                continue;
+            }
+            let url = attr
+                .normalized_value(XmlVersion::Implicit1_0)
+                .map_err(|err| format!("invalid opml: {err}"))?;
+            let url = url.trim();
+            if !url.is_empty() && !urls.iter().any(|known| known == url) {
+                urls.push(url.to_string());
+            }
+        }
+    }
+    Ok(urls)
+}
+
+pub fn write(feeds: &[FeedInfo]) -> String {
+    let mut opml = String::from(concat!(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n",
+        "<opml version=\"2.0\">\n",
+        "  <head>\n",
+        "    <title>podaemon subscriptions</title>\n",
+        "  </head>\n",
+        "  <body>\n",
+    ));
+    for feed in feeds {
+        let title = escape(feed.title.as_deref().unwrap_or(&feed.uri));
+        let url = escape(feed.uri.as_str());
+        opml.push_str(&format!(
+            "    <outline type=\"rss\" text=\"{title}\" title=\"{title}\" xmlUrl=\"{url}\"/>\n"
+        ));
+    }
+    opml.push_str("  </body>\n</opml>\n");
+    opml
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        let feeds = vec![
+            FeedInfo {
+                uri: "https://example.com/feed?a=1&b=2".into(),
+                title: Some("Tom & \"Jerry\"".into()),
+                episodes: 3,
+                refreshing: false,
+                new_episodes: 0,
+            },
+            FeedInfo {
+                uri: "https://example.org/rss".into(),
+                title: None,
+                episodes: 0,
+                refreshing: false,
+                new_episodes: 0,
+            },
+        ];
+        let opml = write(&feeds);
+        assert!(opml.contains("text=\"Tom &amp; &quot;Jerry&quot;\""));
+        assert_eq!(
+            parse(&opml).unwrap(),
+            [
+                "https://example.com/feed?a=1&b=2",
+                "https://example.org/rss"
+            ]
+        );
+    }
+
+    #[test]
+    fn nested_outlines() {
+        let opml = r#"<?xml version="1.0"?>
+            <opml version="2.0"><body>
+              <outline text="News">
+                <outline text="a" type="rss" xmlUrl=" https://a.example/feed "></outline>
+                <outline text="b" type="rss" xmlUrl="https://b.example/feed"/>
+              </outline>
+              <outline text="a again" type="rss" xmlUrl="https://a.example/feed"/>
+              <outline text="no feed"/>
+            </body></opml>"#;
+        assert_eq!(
+            parse(opml).unwrap(),
+            ["https://a.example/feed", "https://b.example/feed"]
+        );
+        assert!(parse("<opml><body><outline xmlUrl=\"x></body>").is_err());
+    }
+}
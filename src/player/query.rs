@@ -0,0 +1,57 @@
+use std::collections::{BTreeSet, HashMap, VecDeque};
+
+use serde::{Deserialize, Serialize};
+
+use super::{state::Playable, stats::Stats};
+
+/// Answer to [`Cmd::Status`](super::Cmd::Status), positions are in seconds.
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct Status {
+    pub state: String,
+    pub playing: bool,
+    pub uri: Option<String>,
+    pub title: Option<String>,
+    pub album: Option<String>,
+    pub position: Option<u64>,
+    pub duration: Option<u64>,
+    pub queue_len: usize,
+    /// Trouble the user should know about, like a state file that had to be
+    /// recovered.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub warning: Option<String>,
+}
+
+/// One element of the queue or of the recently played list.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Entry {
+    pub uri: String,
+    pub playable: Option<Playable>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct FeedInfo {
+    pub uri: String,
+    pub title: Option<String>,
+    pub episodes: usize,
+    #[serde(default)]
+    pub refreshing: bool,
+    /// Episodes that are new and not seen yet.
+    #[serde(default)]
+    pub new_episodes: usize,
+}
+
+/// Answer to [`Cmd::Snapshot`](super::Cmd::Snapshot), everything the terminal
+/// interface shows apart from the episodes.
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct Snapshot {
+    pub status: Status,
+    pub queue: VecDeque<String>,
+    pub recent: VecDeque<String>,
+    pub uris: HashMap<String, Playable>,
+    pub feeds: Vec<FeedInfo>,
+    #[serde(default)]
+    pub stats: Stats,
+    /// Keys of the new episodes, see [`State::new_episodes`](super::state::State::new_episodes).
+    #[serde(default)]
+    pub new_episodes: BTreeSet<String>,
+}
@@ -1,22 +1,31 @@
+use super::cache;
+use super::fetch::{self, fetch, FetchError, Fetched, Schedule, Validators};
+use super::query::{Entry, FeedInfo};
+use crate::data;
+use crate::events::{self, Event};
 use crate::logln;
-use chrono::DateTime;
+use chrono::{DateTime, Utc};
+use futures::FutureExt;
 use gstreamer::ClockTime;
 use reqwest::Client;
 use rss::{Channel, Item};
-use serde::{Deserialize, Serialize};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use serde_json::Value;
 use std::cmp::Ordering;
-use std::collections::{BTreeSet, HashMap, VecDeque};
+use std::collections::{BTreeSet, HashMap, HashSet, VecDeque};
 use std::error::Error;
-use std::fs::File;
-use std::io::{BufReader, BufWriter};
-use std::sync::{Arc, Mutex, RwLock};
-use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use std::fs::{self, File};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{
+    atomic::{self, AtomicBool},
+    Arc, Mutex, RwLock,
+};
+use std::time::{SystemTime, UNIX_EPOCH};
 use tokio::sync::mpsc::{self, channel};
-use tokio::{select, time};
+use tokio::{select, sync::Notify, time};
 
-const FILE: &str = "state";
-
-#[derive(Serialize, Deserialize, Debug, Eq, PartialEq)]
+#[derive(Serialize, Deserialize, Debug, Clone, Default, Eq, PartialEq)]
 pub struct Playable {
     pub source: Option<String>,
     pub title: Option<String>,
@@ -24,8 +33,43 @@ pub struct Playable {
     pub updated: Option<u64>,
     pub progress: Option<u64>,
     pub length: Option<u64>,
+    /// When it was last listened to the end.
+    pub finished: Option<u64>,
+    /// How often it was listened to the end.
+    #[serde(default)]
+    pub play_count: u64,
+}
+
+/// Where the user is with an episode, see [`Playable::status`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EpisodeStatus {
+    Unplayed,
+    InProgress,
+    Played,
 }
+
 impl Playable {
+    /// A played episode that is started again counts as in progress.
+    pub fn status(&self) -> EpisodeStatus {
+        if self.progress.unwrap_or_default() > 0 {
+            EpisodeStatus::InProgress
+        } else if self.finished.is_some() {
+            EpisodeStatus::Played
+        } else {
+            EpisodeStatus::Unplayed
+        }
+    }
+
+    /// What the Episodes tab shows in its "State" column.
+    pub fn status_string(&self) -> String {
+        match self.status() {
+            EpisodeStatus::Unplayed => String::new(),
+            EpisodeStatus::InProgress => self.progress_string(),
+            EpisodeStatus::Played => "played".into(),
+        }
+    }
+
     pub fn progress_string(&self) -> String {
         if let Some(p) = self.progress.map(ClockTime::from_seconds) {
             if let Some(length) = self.length {
@@ -41,7 +85,7 @@ impl Playable {
     }
 }
 
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct Episode {
     pub channel_title: String,
     pub item: Item,
@@ -83,28 +127,121 @@ pub struct RssFeed {
     pub uri: String,
     #[serde(skip)]
     pub channel: Arc<RwLock<Option<Channel>>>,
+    /// Wakes the task observing the feed to refresh it right away.
+    #[serde(skip)]
+    pub refresh: Arc<Notify>,
+    /// Whether a request for the feed is in flight.
+    #[serde(skip)]
+    pub refreshing: Arc<AtomicBool>,
+    /// Set when unsubscribed, the observing task then ends.
+    #[serde(skip)]
+    pub stopped: Arc<AtomicBool>,
 }
 impl RssFeed {
-    pub async fn load(&self, client: &Client) {
-        if let Ok(content) = client.get(&self.uri).send().await {
-            match content.bytes().await {
-                Ok(content) => match Channel::read_from(&content[..]) {
-                    Ok(channel) => {
-                        logln!("updated channel {}", &channel.title);
-                        match self.channel.write() {
-                            Ok(mut guard) => {
-                                let _old = guard.replace(channel);
-                                //TODO: return old and use it to diff ?
-                            }
-                            Err(err) => logln!("failed to lock channel {err}"),
-                        }
-                    }
-                    Err(err) => logln!("failed to read channel {} - {err}", self.uri),
-                },
-                Err(err) => logln!("failed to update {} - {err}", self.uri),
+    pub fn new(uri: String) -> Self {
+        RssFeed {
+            uri,
+            channel: Arc::new(RwLock::new(None)),
+            refresh: Arc::new(Notify::new()),
+            refreshing: Arc::new(AtomicBool::new(false)),
+            stopped: Arc::new(AtomicBool::new(false)),
+        }
+    }
+
+    /// The [keys](item_key) of the episodes it lists.
+    pub fn keys(&self) -> Vec<String> {
+        match self.channel.read() {
+            Ok(channel) => channel.as_ref().map(keys_of).unwrap_or_default(),
+            Err(err) => {
+                logln!("failed to lock channel {err}");
+                Vec::new()
             }
         }
     }
+
+    /// Wakes the observing task to fetch the feed now. Dropped while a
+    /// request is in flight, it is as fresh as it gets.
+    pub fn request_refresh(&self) -> bool {
+        if self.refreshing.load(atomic::Ordering::Relaxed) {
+            return false;
+        }
+        self.refresh.notify_one();
+        true
+    }
+
+    pub fn stop(&self) {
+        self.stopped.store(true, atomic::Ordering::Relaxed);
+        self.refresh.notify_one();
+    }
+
+    fn is_stopped(&self) -> bool {
+        self.stopped.load(atomic::Ordering::Relaxed)
+    }
+
+    /// Fetches and parses the feed if it changed since `validators` were
+    /// received and caches it in `cache_dir`. If `channel` was updated returns
+    /// the keys of the episodes that were not in it before, none the first
+    /// time the feed is known.
+    pub async fn load(
+        &self,
+        client: &Client,
+        validators: &mut Validators,
+        cache_dir: &Path,
+    ) -> Result<Option<Vec<String>>, FetchError> {
+        let channel = match fetch(client, &self.uri, validators).await? {
+            // unsubscribed while the request was in flight
+            Fetched::Modified(..) if self.is_stopped() => return Ok(None),
+            Fetched::Modified(channel, content) => {
+                if let Err(err) = cache::store(cache_dir, &self.uri, &content, validators) {
+                    logln!("failed to cache {}: {err}", self.uri);
+                }
+                channel
+            }
+            Fetched::NotModified => return Ok(None),
+        };
+        logln!("updated channel {}", &channel.title);
+        match self.channel.write() {
+            Ok(mut guard) => {
+                let new = match guard.replace(*channel) {
+                    Some(old) => new_items(&old, guard.as_ref().unwrap()),
+                    None => Vec::new(),
+                };
+                Ok(Some(new))
+            }
+            Err(err) => {
+                logln!("failed to lock channel {err}");
+                Ok(None)
+            }
+        }
+    }
+}
+
+/// Identifies an episode across refreshes: its guid, or failing that its
+/// enclosure or link.
+pub fn item_key(item: &Item) -> Option<&str> {
+    item.guid()
+        .map(|guid| guid.value())
+        .or_else(|| item.enclosure().map(|enclosure| enclosure.url()))
+        .or_else(|| item.link())
+}
+
+fn keys_of(channel: &Channel) -> Vec<String> {
+    channel
+        .items
+        .iter()
+        .filter_map(|item| Some(item_key(item)?.to_string()))
+        .collect()
+}
+
+/// Keys of the items in `new` that were not in `old`.
+fn new_items(old: &Channel, new: &Channel) -> Vec<String> {
+    let known: HashSet<&str> = old.items.iter().filter_map(item_key).collect();
+    new.items
+        .iter()
+        .filter_map(item_key)
+        .filter(|key| !known.contains(key))
+        .map(str::to_string)
+        .collect()
 }
 
 pub fn get_time() -> u64 {
@@ -127,6 +264,14 @@ pub struct State {
     pub queue: VecDeque<String>,
     #[serde(default = "new_recent")]
     pub recent: VecDeque<String>,
+    /// [Keys](item_key) of episodes that appeared in a refresh and were not
+    /// seen yet, shared with the refresh thread.
+    #[serde(skip)]
+    pub new_episodes: Arc<Mutex<BTreeSet<String>>>,
+
+    /// Set when the state could only partially be recovered from disc.
+    #[serde(skip)]
+    pub warning: Option<String>,
 }
 
 fn new_rss_feeds() -> Mutex<Vec<Arc<RssFeed>>> {
@@ -137,53 +282,140 @@ fn new_recent() -> VecDeque<String> {
     VecDeque::with_capacity(32)
 }
 
+/// The elements of the array `value` that parse, counting the others in `lost`.
+fn salvage_items<T: DeserializeOwned>(value: Option<Value>, lost: &mut usize) -> Vec<T> {
+    let items = match value {
+        Some(Value::Array(items)) => items,
+        None | Some(Value::Null) => return Vec::new(),
+        Some(_) => {
+            *lost += 1;
+            return Vec::new();
+        }
+    };
+    items
+        .into_iter()
+        .filter_map(|item| serde_json::from_value(item).map_err(|_| *lost += 1).ok())
+        .collect()
+}
+
 impl State {
-    pub fn from_disc2(file: &str) -> Result<Self, Box<dyn Error>> {
-        let mut state = if let Ok(file) = File::open(file) {
-            let reader = BufReader::new(file);
-            serde_json::from_reader(reader)?
-        } else {
-            State {
-                rss_feeds: new_rss_feeds(),
-                recent: new_recent(),
-                queue: VecDeque::new(),
-                uris: HashMap::new(),
-            }
+    fn empty() -> Self {
+        State {
+            rss_feeds: new_rss_feeds(),
+            recent: new_recent(),
+            queue: VecDeque::new(),
+            uris: HashMap::new(),
+            new_episodes: Arc::default(),
+            warning: None,
+        }
+    }
+
+    /// Reads the state from `file`. A file that does not parse is moved
+    /// aside and as much as possible is recovered, see [`State::warning`].
+    pub fn from_disc2(file: &Path) -> Result<Self, Box<dyn Error>> {
+        let mut state = match fs::read(file) {
+            Ok(content) => match serde_json::from_slice(&content) {
+                Ok(state) => state,
+                Err(err) => {
+                    logln!("failed to parse {}: {err}", file.display());
+                    Self::recover(file, &content)
+                }
+            },
+            Err(err) if err.kind() == io::ErrorKind::NotFound => State::empty(),
+            Err(err) => return Err(err.into()),
         };
         state.recent.reserve(32);
         Ok(state)
     }
 
-    pub fn from_disc() -> Result<Self, Box<dyn Error>> {
-        Self::from_disc2(FILE)
+    /// Quarantines `file` as `<file>.corrupt-<time>`. Then salvages what still
+    /// parses from it, or if it is not JSON at all takes the newest backup.
+    fn recover(file: &Path, content: &[u8]) -> Self {
+        let quarantine = data::quarantine_path(file);
+        let moved = match fs::rename(file, &quarantine) {
+            Ok(()) => format!("moved it to {}", quarantine.display()),
+            Err(err) => format!("failed to move it aside: {err}"),
+        };
+
+        let (mut state, recovered) = match serde_json::from_slice(content) {
+            Ok(value) => Self::salvage(value),
+            Err(_) => match Self::from_backup(file) {
+                Some((state, backup)) => (state, format!("restored {}", backup.display())),
+                None => (State::empty(), "nothing could be recovered".into()),
+            },
+        };
+        let warning = format!("state file was corrupt, {moved}; {recovered}");
+        logln!("{warning}");
+        state.warning = Some(warning);
+        state
+    }
+
+    fn from_backup(file: &Path) -> Option<(Self, PathBuf)> {
+        (1..=data::backups()).find_map(|n| {
+            let backup = data::backup_path(file, n);
+            let state = serde_json::from_slice(&fs::read(&backup).ok()?).ok()?;
+            Some((state, backup))
+        })
     }
 
-    pub fn to_disc(&self) -> Result<(), Box<dyn Error>> {
-        let file = File::create(FILE)?;
-        let writer = BufWriter::new(file);
-        serde_json::to_writer_pretty(writer, &self)?;
-        Ok(())
+    /// Recovers each feed, playable and queue entry that parses on its own.
+    fn salvage(value: Value) -> (Self, String) {
+        let mut state = State::empty();
+        let Value::Object(mut fields) = value else {
+            return (state, "nothing could be recovered".into());
+        };
+        let mut lost = 0;
+        let feeds: Vec<RssFeed> = salvage_items(fields.remove("rss_feeds"), &mut lost);
+        state.rss_feeds = Mutex::new(feeds.into_iter().map(Arc::new).collect());
+        state.queue = salvage_items(fields.remove("queue"), &mut lost).into();
+        state.recent = salvage_items(fields.remove("recent"), &mut lost).into();
+        match fields.remove("uris") {
+            Some(Value::Object(uris)) => {
+                for (uri, playable) in uris {
+                    match serde_json::from_value(playable) {
+                        Ok(playable) => {
+                            state.uris.insert(uri, playable);
+                        }
+                        Err(_) => lost += 1,
+                    }
+                }
+            }
+            None | Some(Value::Null) => {}
+            Some(_) => lost += 1,
+        }
+        let recovered = format!(
+            "recovered {} feeds, {} episodes and {} queued, {lost} entries lost",
+            state.feed_infos().len(),
+            state.uris.len(),
+            state.queue.len(),
+        );
+        (state, recovered)
     }
 
     pub fn insert_playable(&mut self, uri: String, playable: Playable) {
         self.uris.insert(uri, playable);
     }
 
-    pub fn update_playable(
-        &mut self,
-        uri: String,
-        new @ Playable {
-            title: _,
-            album: _,
-            progress: _,
-            length: _,
-            source: _,
-            updated: new_time,
-        }: Playable,
-    ) {
+    /// Takes over the progress of `new` if it is newer than what we have. A
+    /// progress at the end, as other devices report finished episodes, marks
+    /// the episode played.
+    pub fn update_playable(&mut self, uri: String, mut new: Playable) {
+        let new_time = new.updated;
+        let completed = matches!((new.progress, new.length),
+            (Some(progress), Some(length)) if length > 0 && progress >= length);
+        if completed {
+            new.progress = None;
+            new.finished = new_time;
+        }
         match self.uris.get(&uri) {
             Some(old) => {
                 if new_time > old.updated {
+                    // listened to the end once more
+                    let play_count = if completed && new.finished != old.finished {
+                        old.play_count + 1
+                    } else {
+                        old.play_count
+                    };
                     self.uris.insert(
                         uri,
                         Playable {
@@ -193,18 +425,44 @@ impl State {
                             progress: new.progress,
                             updated: new_time,
                             length: new.length,
+                            finished: new.finished.or(old.finished),
+                            play_count,
                         },
                     );
                 }
             }
             None => {
+                if completed {
+                    new.play_count = new.play_count.max(1);
+                }
                 self.uris.insert(uri, new);
             }
         }
     }
 
-    pub fn reset_pos(&mut self, uri: &str) {
-        self.uris.remove(uri);
+    /// Marks `uri` as listened to the end at `time`, keeping what is known
+    /// about it. Playing it again starts from the beginning.
+    pub fn mark_played(&mut self, uri: &str, time: u64) {
+        let playable = self.uris.entry(uri.to_string()).or_default();
+        playable.progress = None;
+        playable.finished = Some(time);
+        playable.updated = Some(time);
+    }
+
+    pub fn mark_unplayed(&mut self, uri: &str, time: u64) {
+        if let Some(playable) = self.uris.get_mut(uri) {
+            playable.progress = None;
+            playable.finished = None;
+            playable.updated = Some(time);
+        }
+    }
+
+    /// Like [`State::mark_played`], for when playback reached the end.
+    pub fn finish(&mut self, uri: &str, time: u64) {
+        self.mark_played(uri, time);
+        if let Some(playable) = self.uris.get_mut(uri) {
+            playable.play_count += 1;
+        }
     }
 
     pub fn queue(&mut self, uri: &str) {
@@ -233,6 +491,105 @@ impl State {
     pub fn get_pos(&self, uri: &str) -> Option<u64> {
         self.uris.get(uri).and_then(|p| p.progress)
     }
+
+    pub fn entries(&self, uris: &VecDeque<String>) -> Vec<Entry> {
+        uris.iter()
+            .map(|uri| Entry {
+                uri: uri.clone(),
+                playable: self.uris.get(uri).cloned(),
+            })
+            .collect()
+    }
+
+    pub fn feed_infos(&self) -> Vec<FeedInfo> {
+        let Ok(feeds) = self.rss_feeds.lock() else {
+            return Vec::new();
+        };
+        feeds
+            .iter()
+            .map(|feed| {
+                let channel = feed.channel.read();
+                let channel = channel.as_deref().ok().and_then(Option::as_ref);
+                let new_episodes = channel.map_or(0, |c| {
+                    c.items.iter().filter(|item| self.is_new(item)).count()
+                });
+                FeedInfo {
+                    uri: feed.uri.clone(),
+                    title: channel.map(|c| c.title.clone()),
+                    episodes: channel.map(|c| c.items.len()).unwrap_or_default(),
+                    refreshing: feed.refreshing.load(atomic::Ordering::Relaxed),
+                    new_episodes,
+                }
+            })
+            .collect()
+    }
+
+    /// Forgets new episodes that are in none of the feeds any more. Only once
+    /// every feed is loaded, until then their episodes are unknown.
+    pub fn prune_new_episodes(&self) {
+        let Ok(feeds) = self.rss_feeds.lock() else {
+            return;
+        };
+        let mut keys = HashSet::new();
+        for feed in feeds.iter() {
+            let Ok(channel) = feed.channel.read() else {
+                return;
+            };
+            let Some(channel) = channel.as_ref() else {
+                return;
+            };
+            keys.extend(keys_of(channel));
+        }
+        if let Ok(mut new_episodes) = self.new_episodes.lock() {
+            new_episodes.retain(|key| keys.contains(key));
+        }
+    }
+
+    pub fn is_new(&self, item: &Item) -> bool {
+        let Some(key) = item_key(item) else {
+            return false;
+        };
+        match self.new_episodes.lock() {
+            Ok(new_episodes) => new_episodes.contains(key),
+            Err(_) => false,
+        }
+    }
+
+    /// Marks the episode with enclosure `uri` as seen, if it is new.
+    pub fn mark_seen(&mut self, uri: &str) {
+        let Ok(feeds) = self.rss_feeds.lock() else {
+            return;
+        };
+        let key = feeds.iter().find_map(|feed| {
+            let channel = feed.channel.read().ok()?;
+            let item = channel
+                .as_ref()?
+                .items
+                .iter()
+                .find(|item| item.enclosure().is_some_and(|e| e.url() == uri))?;
+            Some(item_key(item)?.to_string())
+        });
+        if let (Some(key), Ok(mut new_episodes)) = (key, self.new_episodes.lock()) {
+            new_episodes.remove(&key);
+        }
+    }
+
+    /// Marks the episodes of the feed `uri`, or of all feeds, as seen and
+    /// returns how many were new.
+    pub fn mark_feed_seen(&mut self, uri: Option<&str>) -> usize {
+        let keys: Vec<String> = match self.rss_feeds.lock() {
+            Ok(feeds) => feeds
+                .iter()
+                .filter(|feed| uri.is_none() || uri == Some(feed.uri.as_str()))
+                .flat_map(|feed| feed.keys())
+                .collect(),
+            Err(_) => return 0,
+        };
+        match self.new_episodes.lock() {
+            Ok(mut new_episodes) => keys.iter().filter(|key| new_episodes.remove(*key)).count(),
+            Err(_) => 0,
+        }
+    }
 }
 
 #[allow(dead_code)]
@@ -250,20 +607,70 @@ fn debug_item(item: &Item) -> &Item {
     item
 }
 
-pub fn start_refresh_thread(episodes: Arc<Mutex<BTreeSet<Episode>>>) -> mpsc::Sender<Arc<RssFeed>> {
+/// Episodes of one feed after a successful refresh.
+struct Refreshed {
+    feed: Arc<RssFeed>,
+    uri: String,
+    title: String,
+    episodes: Vec<Episode>,
+    /// Keys of the episodes that are new since the previous refresh.
+    new: Vec<String>,
+}
+
+/// Starts the thread that observes the feeds sent to it, collecting their
+/// episodes in `episodes` and the keys of new ones in `new_episodes`.
+pub fn start_refresh_thread(
+    episodes: Arc<Mutex<BTreeSet<Episode>>>,
+    new_episodes: Arc<Mutex<BTreeSet<String>>>,
+) -> mpsc::Sender<Arc<RssFeed>> {
     let (feed_tx, mut feed_rx) = channel::<Arc<RssFeed>>(10);
     tokio::spawn(async move {
-        let (ep_tx, mut ep_rx) = channel::<Episode>(10);
+        let (ep_tx, mut ep_rx) = channel::<Refreshed>(10);
         loop {
             select! {
                 Some(feed) = feed_rx.recv() => {
-                    observe_feed(feed, ep_tx.clone());
+                    observe_feed(feed, cache::dir(), ep_tx.clone());
                 }
-                Some(ep) = ep_rx.recv() => {
-                    match episodes.lock() {
-                        Ok(mut episodes) => { episodes.insert(ep); },
-                        Err(err) => logln!("{err}"),
+                Some(refreshed) = ep_rx.recv() => {
+                    // held until the episodes are added, unsubscribing takes it
+                    // after stopping the feed
+                    let mut episodes = match episodes.lock() {
+                        Ok(episodes) => episodes,
+                        Err(err) => {
+                            logln!("{err}");
+                            continue;
+                        }
+                    };
+                    // sent before the feed was unsubscribed
+                    if refreshed.feed.is_stopped() {
+                        continue;
+                    }
+                    let new = &refreshed.new;
+                    if !new.is_empty() {
+                        match new_episodes.lock() {
+                            Ok(mut new_episodes) => new_episodes.extend(new.iter().cloned()),
+                            Err(err) => logln!("{err}"),
+                        }
+                        logln!("{} new episodes in {}", new.len(), refreshed.title);
+                        let titles = refreshed
+                            .episodes
+                            .iter()
+                            .filter(|ep| item_key(&ep.item).is_some_and(|key| new.iter().any(|k| k == key)))
+                            .map(|ep| ep.item.title().unwrap_or_default().to_string())
+                            .collect();
+                        events::emit(Event::NewEpisodes {
+                            uri: refreshed.uri.clone(),
+                            title: refreshed.title.clone(),
+                            episodes: titles,
+                        });
                     }
+                    episodes.extend(refreshed.episodes);
+                    drop(episodes);
+                    events::emit(Event::FeedRefreshed {
+                        uri: refreshed.uri,
+                        title: refreshed.title,
+                        new_episodes: refreshed.new.len(),
+                    });
                 }
             }
         }
@@ -271,35 +678,280 @@ pub fn start_refresh_thread(episodes: Arc<Mutex<BTreeSet<Episode>>>) -> mpsc::Se
     feed_tx
 }
 
-fn observe_feed(feed: Arc<RssFeed>, tx: mpsc::Sender<Episode>) {
+/// Fetches `feed` on its schedule or when asked to, keeping a copy in `cache_dir`.
+fn observe_feed(feed: Arc<RssFeed>, cache_dir: PathBuf, tx: mpsc::Sender<Refreshed>) {
     tokio::spawn(async move {
-        let mut update_interval = time::interval(Duration::from_secs(3600));
-        let client = Client::builder().user_agent("007").build();
-        match client {
-            Ok(client) => {
-                let mut new_episodes: Vec<Episode> = Vec::new();
-                loop {
-                    update_interval.tick().await;
-                    feed.load(&client).await;
-                    if let Ok(Some(channel)) = feed.channel.read().as_deref() {
-                        let channel_title = channel.title();
-                        for e in &channel.items {
-                            let ep = Episode {
-                                channel_title: channel_title.to_string(),
-                                item: e.clone(),
-                            };
-                            new_episodes.push(ep);
+        let mut validators = Validators::default();
+        // show what we had until the feed is fetched
+        if let Some((channel, cached)) = cache::load(&cache_dir, &feed.uri) {
+            match feed.channel.write() {
+                Ok(mut guard) => {
+                    guard.get_or_insert(channel);
+                    validators = cached;
+                }
+                Err(err) => logln!("failed to lock channel {err}"),
+            }
+            send_episodes(&feed, &tx, Vec::new()).await;
+        }
+        let mut schedule = Schedule::from_env();
+        match fetch::client() {
+            Ok(client) => loop {
+                // a refresh asked for since waking is answered by this fetch
+                let _ = feed.refresh.notified().now_or_never();
+                if feed.is_stopped() {
+                    logln!("stopped observing {}", feed.uri);
+                    return;
+                }
+                set_refreshing(&feed, true);
+                let loaded = feed.load(&client, &mut validators, &cache_dir).await;
+                set_refreshing(&feed, false);
+                let delay = match loaded {
+                    Ok(new) => {
+                        if let Some(new) = new.filter(|_| !feed.is_stopped()) {
+                            send_episodes(&feed, &tx, new).await;
                         }
+                        let channel = feed.channel.read();
+                        let channel = channel.as_deref().ok().and_then(Option::as_ref);
+                        schedule.succeeded(channel, Utc::now())
                     }
-
-                    while let Some(ep) = new_episodes.pop() {
-                        if let Err(err) = tx.send(ep).await {
-                            logln!("failed to send ep: {err}")
-                        }
+                    Err(err) => {
+                        let delay = schedule.failed(&err);
+                        logln!(
+                            "failed to update {}: {err}, retrying in {}s",
+                            feed.uri,
+                            delay.as_secs()
+                        );
+                        delay
                     }
+                };
+                select! {
+                    _ = time::sleep(delay) => {}
+                    _ = feed.refresh.notified() => {}
                 }
-            }
+            },
             Err(err) => logln!("Failed to init reqwest client: {err}"),
         }
     });
 }
+
+fn set_refreshing(feed: &RssFeed, refreshing: bool) {
+    feed.refreshing.store(refreshing, atomic::Ordering::Relaxed);
+    events::emit(Event::FeedRefreshing {
+        uri: feed.uri.clone(),
+        refreshing,
+    });
+}
+
+async fn send_episodes(feed: &Arc<RssFeed>, tx: &mpsc::Sender<Refreshed>, new: Vec<String>) {
+    let refreshed = if let Ok(Some(channel)) = feed.channel.read().as_deref() {
+        let channel_title = channel.title();
+        Refreshed {
+            feed: Arc::clone(feed),
+            uri: feed.uri.clone(),
+            title: channel_title.to_string(),
+            episodes: channel
+                .items
+                .iter()
+                .map(|e| Episode {
+                    channel_title: channel_title.to_string(),
+                    item: e.clone(),
+                })
+                .collect(),
+            new,
+        }
+    } else {
+        return;
+    };
+
+    if let Err(err) = tx.send(refreshed).await {
+        logln!("failed to send episodes: {err}")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn recover_corrupt_state() {
+        let dir = std::env::temp_dir().join(format!("podaemon-state-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let file = dir.join("state");
+        let quarantined = || {
+            fs::read_dir(&dir)
+                .unwrap()
+                .filter(|entry| {
+                    let name = entry.as_ref().unwrap().file_name();
+                    name.to_string_lossy().starts_with("state.corrupt-")
+                })
+                .count()
+        };
+
+        fs::write(
+            &file,
+            r#"{
+                "rss_feeds": [{"uri": "https://a.example/feed"}, {"url": 1}],
+                "uris": {"ep1": {"progress": 10, "updated": 5}, "ep2": {"progress": "x"}},
+                "queue": ["ep1", 3],
+                "recent": "nope"
+            }"#,
+        )
+        .unwrap();
+        let state = State::from_disc2(&file).unwrap();
+        assert_eq!(state.feed_infos().len(), 1);
+        assert_eq!(state.uris["ep1"].progress, Some(10));
+        assert_eq!(state.uris.len(), 1);
+        assert_eq!(state.queue, ["ep1"]);
+        assert!(state.recent.is_empty());
+        let warning = state.warning.unwrap();
+        assert!(warning.contains("1 feeds, 1 episodes and 1 queued, 4 entries lost"));
+        assert!(!file.exists());
+        assert_eq!(quarantined(), 1);
+
+        // not even JSON, without backups there is nothing to recover
+        fs::write(&file, r#"{"uris": {"ep1": {"#).unwrap();
+        let state = State::from_disc2(&file).unwrap();
+        assert!(state.uris.is_empty());
+        assert!(state
+            .warning
+            .unwrap()
+            .contains("nothing could be recovered"));
+        assert_eq!(quarantined(), 2);
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn played_status() {
+        let mut state = State::empty();
+        let progress = |progress, updated| Playable {
+            progress: Some(progress),
+            length: Some(60),
+            updated: Some(updated),
+            ..Playable::default()
+        };
+        state.update_playable("ep".into(), progress(30, 1));
+        assert_eq!(state.uris["ep"].status(), EpisodeStatus::InProgress);
+
+        state.finish("ep", 2);
+        assert_eq!(state.uris["ep"].status(), EpisodeStatus::Played);
+        assert_eq!(state.uris["ep"].play_count, 1);
+
+        // listening again keeps it played and counted
+        state.update_playable("ep".into(), progress(10, 3));
+        assert_eq!(state.uris["ep"].status(), EpisodeStatus::InProgress);
+        assert_eq!(state.uris["ep"].finished, Some(2));
+        state.update_playable("ep".into(), progress(60, 4));
+        assert_eq!(state.uris["ep"].status(), EpisodeStatus::Played);
+        assert_eq!(state.uris["ep"].finished, Some(4));
+        assert_eq!(state.uris["ep"].play_count, 2);
+        // the same completion reported again
+        state.update_playable("ep".into(), progress(60, 4));
+        assert_eq!(state.uris["ep"].play_count, 2);
+
+        state.mark_unplayed("ep", 5);
+        assert_eq!(state.uris["ep"].status(), EpisodeStatus::Unplayed);
+        assert_eq!(state.uris["ep"].play_count, 2);
+
+        // finished on a peer before it was ever played here
+        state.update_playable("other".into(), progress(60, 6));
+        assert_eq!(state.uris["other"].status(), EpisodeStatus::Played);
+        assert_eq!(state.uris["other"].play_count, 1);
+    }
+
+    #[test]
+    fn new_episodes() {
+        let channel = |items: &str| {
+            let xml = format!(
+                r#"<rss version="2.0"><channel><title>Show</title>{items}</channel></rss>"#
+            );
+            Channel::read_from(xml.as_bytes()).unwrap()
+        };
+        let one = r#"<item><guid>show-1</guid><enclosure url="https://a.example/1.mp3" length="1" type="audio/mpeg"/></item>"#;
+        let two = r#"<item><guid>show-2</guid><enclosure url="https://a.example/2.mp3" length="1" type="audio/mpeg"/></item>"#;
+        let three = r#"<item><enclosure url="https://a.example/3.mp3" length="1" type="audio/mpeg"/></item>"#;
+        let old = channel(one);
+        let new = channel(&format!("{three}{two}{one}"));
+        assert_eq!(new_items(&old, &new), ["https://a.example/3.mp3", "show-2"]);
+        assert!(new_items(&new, &old).is_empty());
+
+        let mut state = State::empty();
+        let feed = RssFeed::new("https://a.example/feed".into());
+        *feed.channel.write().unwrap() = Some(new);
+        state.rss_feeds.lock().unwrap().push(Arc::new(feed));
+        state
+            .new_episodes
+            .lock()
+            .unwrap()
+            .extend(["https://a.example/3.mp3".to_string(), "show-2".to_string()]);
+        assert_eq!(state.feed_infos()[0].new_episodes, 2);
+
+        state.mark_seen("https://a.example/2.mp3");
+        assert_eq!(state.feed_infos()[0].new_episodes, 1);
+        assert_eq!(state.mark_feed_seen(Some("https://b.example/feed")), 0);
+        assert_eq!(state.mark_feed_seen(None), 1);
+        assert!(state.new_episodes.lock().unwrap().is_empty());
+
+        // gone from the feed, but not while a feed is not loaded yet
+        let gone = || ["show-0".to_string(), "show-1".to_string()];
+        state.new_episodes.lock().unwrap().extend(gone());
+        let unloaded = RssFeed::new("https://b.example/feed".into());
+        state.rss_feeds.lock().unwrap().push(Arc::new(unloaded));
+        state.prune_new_episodes();
+        assert_eq!(state.new_episodes.lock().unwrap().len(), 2);
+        state.rss_feeds.lock().unwrap().pop();
+        state.prune_new_episodes();
+        assert_eq!(
+            *state.new_episodes.lock().unwrap(),
+            BTreeSet::from(["show-1".into()])
+        );
+    }
+
+    #[tokio::test]
+    async fn refresh_wakes_observation() {
+        use axum::{routing::get, Router, Server};
+        use std::sync::atomic::AtomicUsize;
+
+        let requests = Arc::new(AtomicUsize::new(0));
+        let counter = Arc::clone(&requests);
+        let app = Router::new().route(
+            "/feed",
+            get(move || async move {
+                counter.fetch_add(1, atomic::Ordering::Relaxed);
+                r#"<rss version="2.0"><channel><title>Show</title></channel></rss>"#
+            }),
+        );
+        let server = Server::bind(&"127.0.0.1:0".parse().unwrap()).serve(app.into_make_service());
+        let feed = Arc::new(RssFeed::new(format!("http://{}/feed", server.local_addr())));
+        tokio::spawn(server);
+
+        let dir = std::env::temp_dir().join(format!("podaemon-observe-{}", std::process::id()));
+        let (tx, mut rx) = channel(1);
+        observe_feed(feed.clone(), dir.clone(), tx);
+        let timeout = std::time::Duration::from_secs(10);
+        time::timeout(timeout, rx.recv()).await.unwrap().unwrap();
+        // the next scheduled fetch is an hour away
+        assert!(feed.request_refresh());
+        time::timeout(timeout, rx.recv()).await.unwrap().unwrap();
+        assert_eq!(requests.load(atomic::Ordering::Relaxed), 2);
+
+        // while a fetch is in flight another one is not queued up
+        feed.refreshing.store(true, atomic::Ordering::Relaxed);
+        assert!(!feed.request_refresh());
+        feed.stop();
+        let _ = fs::remove_dir_all(dir);
+    }
+
+    #[tokio::test]
+    async fn stop_observing() {
+        // nothing listens on the discard port, the fetch fails right away
+        let feed = Arc::new(RssFeed::new("http://127.0.0.1:9/feed".into()));
+        let dir = std::env::temp_dir().join(format!("podaemon-stop-{}", std::process::id()));
+        let (tx, mut rx) = channel(1);
+        observe_feed(feed.clone(), dir, tx);
+        feed.stop();
+        // the task drops its sender when it ends
+        let ended = time::timeout(std::time::Duration::from_secs(10), rx.recv()).await;
+        assert!(ended.unwrap().is_none());
+    }
+}
@@ -0,0 +1,252 @@
+//! Listening statistics. The player records a [`Session`] for every stretch
+//! of playback, from starting to play until pausing, the end of the episode
+//! or going to another one. Times are unix seconds, days and weeks are
+//! counted in local time.
+
+use std::collections::{BTreeMap, HashMap};
+
+use chrono::{DateTime, Local, TimeZone};
+use serde::{Deserialize, Serialize};
+
+use super::state::Playable;
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Session {
+    pub uri: String,
+    /// The podcast, the album of the episode if it is known.
+    pub feed: Option<String>,
+    pub started: u64,
+    pub stopped: u64,
+    /// Positions in the episode, in seconds.
+    pub from: Option<u64>,
+    pub to: Option<u64>,
+}
+
+impl Session {
+    pub fn seconds(&self) -> u64 {
+        self.stopped.saturating_sub(self.started)
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct Total {
+    pub name: String,
+    pub seconds: u64,
+}
+
+/// Answer to [`Cmd::Stats`](super::Cmd::Stats).
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct Stats {
+    pub seconds: u64,
+    pub sessions: usize,
+    /// Most listened to first.
+    pub per_feed: Vec<Total>,
+    /// Newest first, as `2024-01-31`.
+    pub per_day: Vec<Total>,
+    /// Newest first, as ISO weeks like `2024-W05`.
+    pub per_week: Vec<Total>,
+    /// Episodes listened to the end at least once.
+    pub finished: usize,
+    /// How far into the episodes that were listened to one got on average,
+    /// from 0 to 1. Only episodes with a known length count.
+    pub completion: Option<f64>,
+}
+
+impl Stats {
+    pub fn new<'a>(
+        sessions: impl IntoIterator<Item = &'a Session>,
+        uris: &HashMap<String, Playable>,
+    ) -> Self {
+        Stats::in_zone(sessions, uris, &Local)
+    }
+
+    fn in_zone<'a, Tz: TimeZone>(
+        sessions: impl IntoIterator<Item = &'a Session>,
+        uris: &HashMap<String, Playable>,
+        zone: &Tz,
+    ) -> Self
+    where
+        Tz::Offset: std::fmt::Display,
+    {
+        let mut stats = Stats {
+            finished: uris.values().filter(|p| p.play_count > 0).count(),
+            ..Stats::default()
+        };
+        let mut per_feed = HashMap::new();
+        let mut per_day = BTreeMap::new();
+        let mut per_week = BTreeMap::new();
+        // furthest position reached per episode
+        let mut reached: HashMap<&str, u64> = HashMap::new();
+        for session in sessions {
+            let seconds = session.seconds();
+            stats.seconds += seconds;
+            stats.sessions += 1;
+            let feed = session.feed.as_deref().unwrap_or("unknown");
+            *per_feed.entry(feed.to_string()).or_default() += seconds;
+            if let Some(time) = DateTime::from_timestamp(session.started as i64, 0) {
+                let time = time.with_timezone(zone);
+                *per_day
+                    .entry(time.format("%Y-%m-%d").to_string())
+                    .or_default() += seconds;
+                *per_week
+                    .entry(time.format("%G-W%V").to_string())
+                    .or_default() += seconds;
+            }
+            let furthest = reached.entry(&session.uri).or_default();
+            *furthest = (*furthest).max(session.to.unwrap_or_default());
+        }
+
+        let completions: Vec<f64> = reached
+            .into_iter()
+            .filter_map(|(uri, furthest)| {
+                let playable = uris.get(uri)?;
+                let length = playable.length.filter(|length| *length > 0)?;
+                if playable.play_count > 0 {
+                    return Some(1.0);
+                }
+                let furthest = furthest.max(playable.progress.unwrap_or_default());
+                Some((furthest as f64 / length as f64).min(1.0))
+            })
+            .collect();
+        if !completions.is_empty() {
+            stats.completion = Some(completions.iter().sum::<f64>() / completions.len() as f64);
+        }
+
+        let totals = |map: BTreeMap<String, u64>| {
+            map.into_iter()
+                .rev()
+                .map(|(name, seconds)| Total { name, seconds })
+                .collect()
+        };
+        stats.per_day = totals(per_day);
+        stats.per_week = totals(per_week);
+        let mut per_feed: Vec<Total> = per_feed
+            .into_iter()
+            .map(|(name, seconds)| Total { name, seconds })
+            .collect();
+        per_feed.sort_by(|a, b| b.seconds.cmp(&a.seconds).then(a.name.cmp(&b.name)));
+        stats.per_feed = per_feed;
+        stats
+    }
+}
+
+/// How [`Cmd::ExportStats`](super::Cmd::ExportStats) answers.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ExportFormat {
+    /// The statistics and all sessions.
+    #[default]
+    Json,
+    /// The sessions as [`to_csv`] writes them.
+    Csv,
+}
+
+/// Like `3h 05m`, or `4m` under an hour.
+pub fn duration_string(seconds: u64) -> String {
+    let minutes = seconds / 60;
+    if minutes < 60 {
+        format!("{minutes}m")
+    } else {
+        format!("{}h {:02}m", minutes / 60, minutes % 60)
+    }
+}
+
+/// One line per session, with a header.
+pub fn to_csv(sessions: &[Session]) -> String {
+    let mut csv = String::from("uri,feed,started,stopped,seconds,from,to\n");
+    for session in sessions {
+        let number = |n: Option<u64>| n.map(|n| n.to_string()).unwrap_or_default();
+        csv.push_str(&format!(
+            "{},{},{},{},{},{},{}\n",
+            csv_field(&session.uri),
+            csv_field(session.feed.as_deref().unwrap_or_default()),
+            session.started,
+            session.stopped,
+            session.seconds(),
+            number(session.from),
+            number(session.to),
+        ));
+    }
+    csv
+}
+
+fn csv_field(field: &str) -> String {
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::Utc;
+
+    use super::*;
+
+    #[test]
+    fn totals() {
+        let session = |uri: &str, feed: &str, started, seconds, to| Session {
+            uri: uri.into(),
+            feed: Some(feed.into()),
+            started,
+            stopped: started + seconds,
+            from: Some(0),
+            to: Some(to),
+        };
+        // Friday 2024-02-02 and Monday 2024-02-05, 10:00 UTC
+        let (friday, monday) = (1_706_868_000, 1_707_127_200);
+        let sessions = [
+            session("a1", "A, the show", friday, 600, 600),
+            session("b1", "B", friday + 3600, 1200, 1200),
+            session("a1", "A, the show", monday, 300, 900),
+        ];
+        let uris = HashMap::from([
+            (
+                "a1".to_string(),
+                Playable {
+                    length: Some(1800),
+                    ..Playable::default()
+                },
+            ),
+            (
+                "b1".to_string(),
+                Playable {
+                    length: Some(1200),
+                    play_count: 1,
+                    ..Playable::default()
+                },
+            ),
+        ]);
+
+        let stats = Stats::in_zone(&sessions, &uris, &Utc);
+        assert_eq!(
+            (stats.seconds, stats.sessions, stats.finished),
+            (2100, 3, 1)
+        );
+        let names = |totals: &[Total]| -> Vec<(String, u64)> {
+            totals.iter().map(|t| (t.name.clone(), t.seconds)).collect()
+        };
+        assert_eq!(
+            names(&stats.per_feed),
+            [("B".into(), 1200), ("A, the show".into(), 900)]
+        );
+        assert_eq!(
+            names(&stats.per_day),
+            [("2024-02-05".into(), 300), ("2024-02-02".into(), 1800)]
+        );
+        assert_eq!(
+            names(&stats.per_week),
+            [("2024-W06".into(), 300), ("2024-W05".into(), 1800)]
+        );
+        // half of a1, all of b1
+        assert_eq!(stats.completion, Some(0.75));
+
+        let csv = to_csv(&sessions[..1]);
+        assert_eq!(
+            csv,
+            "uri,feed,started,stopped,seconds,from,to\n\
+             a1,\"A, the show\",1706868000,1706868600,600,0,600\n"
+        );
+    }
+}
@@ -0,0 +1,877 @@
+//! The library in SQLite, `podaemon.db` in the [data dir](crate::data).
+//!
+//! The player keeps working on the in-memory [`State`]. [`Store::save`]
+//! compares it with what was saved last and writes only the difference, in
+//! one transaction. The JSON `state` file of earlier versions is imported on
+//! first run and then renamed to `state.imported`. Listening sessions are
+//! only ever appended, [`Store::record`] writes them right away. New episodes
+//! are kept until seen, by [key](super::state::item_key). Progress updates
+//! that could not be pushed to a [peer](crate::peers) before shutdown are kept
+//! until the next start.
+//!
+//! The schema version is kept in `PRAGMA user_version`. Opening an older
+//! database runs the [`MIGRATIONS`] it is missing, a database of a newer
+//! podaemon is refused instead of being written in a format it does not know.
+//!
+//! Each start backs the database up first, see [`data`](crate::data). One that
+//! cannot be read is moved aside and replaced by its newest usable backup, or
+//! an empty one, which [`State::warning`] tells.
+
+use std::{
+    collections::{BTreeSet, HashMap, VecDeque},
+    error::Error,
+    fmt, fs,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex, PoisonError},
+};
+
+use rusqlite::{params, Connection, DatabaseName, OptionalExtension, Transaction};
+
+use crate::{data, logln};
+
+use super::{
+    cmd::UpdateArgs,
+    query::FeedInfo,
+    state::{Playable, RssFeed, State},
+    stats::Session,
+};
+
+const DB: &str = "podaemon.db";
+const JSON: &str = "state";
+
+/// Schema version written by this build.
+pub const VERSION: usize = 5;
+
+type Migration = fn(&Transaction) -> rusqlite::Result<()>;
+
+/// `MIGRATIONS[n]` upgrades the schema from version `n` to `n + 1`. Released
+/// migrations are never changed, a new schema gets a new one.
+const MIGRATIONS: [Migration; VERSION] = [
+    create_tables,
+    add_played,
+    add_sessions,
+    add_new_episodes,
+    add_unsent_updates,
+];
+
+// `IF NOT EXISTS` since databases from before versioning have version 0
+const SCHEMA_V1: &str = "
+    CREATE TABLE IF NOT EXISTS meta (
+        key TEXT PRIMARY KEY,
+        value TEXT NOT NULL
+    );
+    CREATE TABLE IF NOT EXISTS feeds (
+        uri TEXT PRIMARY KEY,
+        title TEXT
+    );
+    CREATE TABLE IF NOT EXISTS episodes (
+        uri TEXT PRIMARY KEY,
+        title TEXT,
+        album TEXT,
+        source TEXT,
+        length INTEGER
+    );
+    CREATE TABLE IF NOT EXISTS positions (
+        uri TEXT PRIMARY KEY,
+        progress INTEGER,
+        updated INTEGER
+    );
+    CREATE TABLE IF NOT EXISTS queue (
+        position INTEGER PRIMARY KEY,
+        uri TEXT NOT NULL
+    );
+    CREATE TABLE IF NOT EXISTS history (
+        position INTEGER PRIMARY KEY,
+        uri TEXT NOT NULL
+    );
+";
+
+/// What the database holds, as of the last load or save.
+#[derive(Debug, Default)]
+struct Saved {
+    feeds: HashMap<String, Option<String>>,
+    uris: HashMap<String, Playable>,
+    queue: VecDeque<String>,
+    recent: VecDeque<String>,
+    new_episodes: BTreeSet<String>,
+}
+
+/// A database written by a newer podaemon, it is left untouched.
+#[derive(Debug)]
+pub struct NewerVersion(usize);
+
+impl fmt::Display for NewerVersion {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{DB} has schema version {} but this podaemon only knows up to \
+             {VERSION}, refusing to use it; upgrade podaemon or move the file away",
+            self.0
+        )
+    }
+}
+
+impl Error for NewerVersion {}
+
+#[derive(Debug)]
+pub struct Store {
+    // only ever used through `&mut self`, the mutex just makes `Store` Sync
+    conn: Mutex<Connection>,
+    saved: Saved,
+}
+
+/// Opens the library in the data dir, importing the JSON state if needed.
+pub fn open() -> Result<(Store, State), Box<dyn Error>> {
+    let path = data::path(DB);
+    let (mut store, warning) = match Store::open(&path) {
+        Ok(mut store) => {
+            store.back_up(&path);
+            (store, None)
+        }
+        Err(err) if err.is::<NewerVersion>() => return Err(err),
+        Err(err) => {
+            let (store, warning) = Store::recover(&path, &*err)?;
+            (store, Some(warning))
+        }
+    };
+    if store.imported()? {
+        let mut state = store.load()?;
+        state.warning = warning;
+        return Ok((store, state));
+    }
+    let valid = |content: &[u8]| serde_json::from_slice::<State>(content).is_ok();
+    let json = data::legacy_path(JSON, valid);
+    if !json.exists() {
+        let mut state = store.load()?;
+        state.warning = warning;
+        return Ok((store, state));
+    }
+
+    let state = State::from_disc2(&json)?;
+    store.import(&state)?;
+    logln!("imported {} into {DB}", json.display());
+    let mut imported = json.clone().into_os_string();
+    imported.push(".imported");
+    if let Err(err) = fs::rename(&json, &imported) {
+        logln!("failed to rename {JSON}: {err}");
+    }
+    Ok((store, state))
+}
+
+impl Store {
+    pub fn open(path: &Path) -> Result<Self, Box<dyn Error>> {
+        if let Some(dir) = path.parent() {
+            // rusqlite reports a missing dir as "unable to open database file"
+            let _ = fs::create_dir_all(dir);
+        }
+        Store::init(Connection::open(path)?)
+    }
+
+    #[cfg(test)]
+    fn open_in_memory() -> Result<Self, Box<dyn Error>> {
+        Store::init(Connection::open_in_memory()?)
+    }
+
+    fn init(mut conn: Connection) -> Result<Self, Box<dyn Error>> {
+        // before migrating, nothing is written to a damaged database
+        let check: String = conn.query_row("PRAGMA quick_check", [], |row| row.get(0))?;
+        if check != "ok" {
+            return Err(format!("integrity check failed: {check}").into());
+        }
+        migrate(&mut conn)?;
+        conn.pragma_update(None, "journal_mode", "WAL")?;
+        Ok(Store {
+            conn: Mutex::new(conn),
+            saved: Saved::default(),
+        })
+    }
+
+    /// Keeps a copy of the database at `path` as `<name>.1`, moving up the
+    /// older ones.
+    fn back_up(&mut self, path: &Path) {
+        let backups = data::backups();
+        if backups == 0 {
+            return;
+        }
+        let conn = self.conn.get_mut().unwrap_or_else(PoisonError::into_inner);
+        let backed_up = data::rotate_backups(path, backups)
+            .map_err(Box::<dyn Error>::from)
+            .and_then(|()| {
+                let newest = data::backup_path(path, 1);
+                Ok(conn.backup(DatabaseName::Main, newest, None)?)
+            });
+        if let Err(err) = backed_up {
+            logln!("failed to back up {DB}: {err}");
+        }
+    }
+
+    /// Moves the database at `path` aside, as it failed to open with `err`,
+    /// and opens the newest backup that does or else an empty one. Returns
+    /// the warning that tells what happened.
+    fn recover(path: &Path, err: &dyn Error) -> Result<(Self, String), Box<dyn Error>> {
+        let quarantine = data::quarantine_path(path);
+        let moved = match move_db(path, &quarantine) {
+            Ok(()) => format!("moved it to {}", quarantine.display()),
+            Err(err) => format!("failed to move it aside: {err}"),
+        };
+        let (store, recovered) = match Store::from_backup(path) {
+            Some((store, backup)) => (store, format!("restored {}", backup.display())),
+            None => (Store::open(path)?, "started an empty one".into()),
+        };
+        let warning = format!("{DB} could not be opened ({err}), {moved}; {recovered}");
+        logln!("{warning}");
+        Ok((store, warning))
+    }
+
+    fn from_backup(path: &Path) -> Option<(Self, PathBuf)> {
+        (1..=data::backups()).find_map(|n| {
+            let backup = data::backup_path(path, n);
+            fs::copy(&backup, path).ok()?;
+            match Store::open(path) {
+                Ok(store) => Some((store, backup)),
+                Err(err) => {
+                    logln!("failed to restore {}: {err}", backup.display());
+                    for file in db_files(path) {
+                        let _ = fs::remove_file(file);
+                    }
+                    None
+                }
+            }
+        })
+    }
+
+    fn imported(&mut self) -> rusqlite::Result<bool> {
+        self.conn
+            .get_mut()
+            .unwrap_or_else(PoisonError::into_inner)
+            .query_row("SELECT 1 FROM meta WHERE key = 'json_imported'", [], |_| {
+                Ok(())
+            })
+            .optional()
+            .map(|row| row.is_some())
+    }
+
+    fn import(&mut self, state: &State) -> rusqlite::Result<()> {
+        let tx = self
+            .conn
+            .get_mut()
+            .unwrap_or_else(PoisonError::into_inner)
+            .transaction()?;
+        let saved = write(&tx, &self.saved, state)?;
+        tx.execute(
+            "INSERT OR REPLACE INTO meta (key, value) VALUES ('json_imported', '1')",
+            [],
+        )?;
+        tx.commit()?;
+        self.saved = saved;
+        Ok(())
+    }
+
+    pub fn load(&mut self) -> rusqlite::Result<State> {
+        let conn = self.conn.get_mut().unwrap_or_else(PoisonError::into_inner);
+        let mut saved = Saved::default();
+        let mut feeds = Vec::new();
+        let mut stmt = conn.prepare("SELECT uri, title FROM feeds ORDER BY rowid")?;
+        for row in stmt.query_map([], |row| Ok((row.get(0)?, row.get(1)?)))? {
+            let (uri, title): (String, Option<String>) = row?;
+            feeds.push(Arc::new(RssFeed::new(uri.clone())));
+            saved.feeds.insert(uri, title);
+        }
+
+        let mut stmt = conn.prepare(
+            "SELECT e.uri, e.title, e.album, e.source, e.length,
+                    p.progress, p.updated, p.finished, COALESCE(p.play_count, 0)
+             FROM episodes e LEFT JOIN positions p ON p.uri = e.uri",
+        )?;
+        let rows = stmt.query_map([], |row| {
+            let playable = Playable {
+                title: row.get(1)?,
+                album: row.get(2)?,
+                source: row.get(3)?,
+                length: row.get(4)?,
+                progress: row.get(5)?,
+                updated: row.get(6)?,
+                finished: row.get(7)?,
+                play_count: row.get(8)?,
+            };
+            Ok((row.get(0)?, playable))
+        })?;
+        for row in rows {
+            let (uri, playable) = row?;
+            saved.uris.insert(uri, playable);
+        }
+
+        saved.queue = list(conn, "queue")?;
+        saved.recent = list(conn, "history")?;
+        let mut stmt = conn.prepare("SELECT key FROM new_episodes")?;
+        let keys = stmt.query_map([], |row| row.get(0))?;
+        saved.new_episodes = keys.collect::<rusqlite::Result<_>>()?;
+
+        let mut state = State {
+            rss_feeds: Mutex::new(feeds),
+            uris: saved.uris.clone(),
+            queue: saved.queue.clone(),
+            recent: saved.recent.clone(),
+            new_episodes: Arc::new(Mutex::new(saved.new_episodes.clone())),
+            warning: None,
+        };
+        state.recent.reserve(32);
+        self.saved = saved;
+        Ok(state)
+    }
+
+    pub fn sessions(&mut self) -> rusqlite::Result<Vec<Session>> {
+        let conn = self.conn.get_mut().unwrap_or_else(PoisonError::into_inner);
+        let mut stmt = conn.prepare(
+            "SELECT uri, feed, started, stopped, start_pos, end_pos
+             FROM sessions ORDER BY started",
+        )?;
+        let sessions = stmt.query_map([], |row| {
+            Ok(Session {
+                uri: row.get(0)?,
+                feed: row.get(1)?,
+                started: row.get(2)?,
+                stopped: row.get(3)?,
+                from: row.get(4)?,
+                to: row.get(5)?,
+            })
+        })?;
+        sessions.collect()
+    }
+
+    pub fn record(&mut self, session: &Session) -> rusqlite::Result<()> {
+        self.conn
+            .get_mut()
+            .unwrap_or_else(PoisonError::into_inner)
+            .execute(
+                "INSERT INTO sessions (uri, feed, started, stopped, start_pos, end_pos)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+                params![
+                    session.uri,
+                    session.feed,
+                    session.started,
+                    session.stopped,
+                    session.from,
+                    session.to
+                ],
+            )
+            .map(|_| ())
+    }
+
+    /// Writes only the new episodes of `state`, for when a refresh found some.
+    pub fn save_new_episodes(&mut self, state: &State) -> rusqlite::Result<()> {
+        let tx = self
+            .conn
+            .get_mut()
+            .unwrap_or_else(PoisonError::into_inner)
+            .transaction()?;
+        let new_episodes = write_new_episodes(&tx, &self.saved.new_episodes, state)?;
+        tx.commit()?;
+        self.saved.new_episodes = new_episodes;
+        Ok(())
+    }
+
+    /// Updates for peers that were not delivered, as `(peer, update)`.
+    pub fn unsent(&mut self) -> Result<Vec<(String, UpdateArgs)>, Box<dyn Error>> {
+        let conn = self.conn.get_mut().unwrap_or_else(PoisonError::into_inner);
+        let mut stmt = conn.prepare("SELECT peer, uri, playable FROM unsent_updates")?;
+        let rows = stmt.query_map([], |row| {
+            Ok((
+                row.get::<_, String>(0)?,
+                row.get(1)?,
+                row.get::<_, String>(2)?,
+            ))
+        })?;
+        let mut unsent = Vec::new();
+        for row in rows {
+            let (peer, uri, playable) = row?;
+            unsent.push((peer, UpdateArgs(uri, serde_json::from_str(&playable)?)));
+        }
+        Ok(unsent)
+    }
+
+    /// Replaces the undelivered updates with `unsent`.
+    pub fn set_unsent(&mut self, unsent: &[(String, UpdateArgs)]) -> Result<(), Box<dyn Error>> {
+        let tx = self
+            .conn
+            .get_mut()
+            .unwrap_or_else(PoisonError::into_inner)
+            .transaction()?;
+        tx.execute("DELETE FROM unsent_updates", [])?;
+        for (peer, UpdateArgs(uri, playable)) in unsent {
+            tx.execute(
+                "INSERT OR REPLACE INTO unsent_updates (peer, uri, playable) VALUES (?1, ?2, ?3)",
+                params![peer, uri, serde_json::to_string(playable)?],
+            )?;
+        }
+        tx.commit()?;
+        Ok(())
+    }
+
+    /// Writes what changed in `state` since the last save.
+    pub fn save(&mut self, state: &State) -> rusqlite::Result<()> {
+        let tx = self
+            .conn
+            .get_mut()
+            .unwrap_or_else(PoisonError::into_inner)
+            .transaction()?;
+        let saved = write(&tx, &self.saved, state)?;
+        tx.commit()?;
+        self.saved = saved;
+        Ok(())
+    }
+}
+
+fn migrate(conn: &mut Connection) -> Result<(), Box<dyn Error>> {
+    let version: usize = conn.pragma_query_value(None, "user_version", |row| row.get(0))?;
+    if version > VERSION {
+        return Err(NewerVersion(version).into());
+    }
+    for (from, migration) in MIGRATIONS.iter().enumerate().skip(version) {
+        let tx = conn.transaction()?;
+        migration(&tx)?;
+        tx.pragma_update(None, "user_version", from + 1)?;
+        tx.commit()?;
+        logln!("migrated {DB} to schema version {}", from + 1);
+    }
+    Ok(())
+}
+
+/// The database at `path` with its write-ahead log and shared memory index.
+fn db_files(path: &Path) -> [PathBuf; 3] {
+    let with_suffix = |suffix: &str| {
+        let mut name = path.as_os_str().to_owned();
+        name.push(suffix);
+        PathBuf::from(name)
+    };
+    [path.to_path_buf(), with_suffix("-wal"), with_suffix("-shm")]
+}
+
+fn move_db(path: &Path, to: &Path) -> std::io::Result<()> {
+    for (from, to) in db_files(path).iter().zip(db_files(to)) {
+        if from.exists() {
+            fs::rename(from, to)?;
+        }
+    }
+    Ok(())
+}
+
+fn create_tables(tx: &Transaction) -> rusqlite::Result<()> {
+    tx.execute_batch(SCHEMA_V1)
+}
+
+fn add_played(tx: &Transaction) -> rusqlite::Result<()> {
+    tx.execute_batch(
+        "ALTER TABLE positions ADD COLUMN finished INTEGER;
+         ALTER TABLE positions ADD COLUMN play_count INTEGER NOT NULL DEFAULT 0;",
+    )
+}
+
+fn add_sessions(tx: &Transaction) -> rusqlite::Result<()> {
+    tx.execute_batch(
+        "CREATE TABLE sessions (
+            uri TEXT NOT NULL,
+            feed TEXT,
+            started INTEGER NOT NULL,
+            stopped INTEGER NOT NULL,
+            start_pos INTEGER,
+            end_pos INTEGER
+        );
+        CREATE INDEX sessions_started ON sessions (started);",
+    )
+}
+
+fn add_new_episodes(tx: &Transaction) -> rusqlite::Result<()> {
+    tx.execute_batch("CREATE TABLE new_episodes (key TEXT PRIMARY KEY);")
+}
+
+fn add_unsent_updates(tx: &Transaction) -> rusqlite::Result<()> {
+    tx.execute_batch(
+        "CREATE TABLE unsent_updates (
+            peer TEXT NOT NULL,
+            uri TEXT NOT NULL,
+            playable TEXT NOT NULL,
+            PRIMARY KEY (peer, uri)
+        );",
+    )
+}
+
+fn list(conn: &Connection, table: &str) -> rusqlite::Result<VecDeque<String>> {
+    let mut stmt = conn.prepare(&format!("SELECT uri FROM {table} ORDER BY position"))?;
+    let uris = stmt.query_map([], |row| row.get(0))?;
+    uris.collect()
+}
+
+/// Brings the database from `saved` to `state`, returns the new [`Saved`].
+fn write(tx: &Transaction, saved: &Saved, state: &State) -> rusqlite::Result<Saved> {
+    let infos = state.feed_infos();
+    let feeds: HashMap<String, Option<String>> = infos
+        .iter()
+        .map(|feed| (feed.uri.clone(), feed.title.clone()))
+        .collect();
+    for uri in saved.feeds.keys().filter(|uri| !feeds.contains_key(*uri)) {
+        tx.execute("DELETE FROM feeds WHERE uri = ?1", [uri])?;
+    }
+    // in order, the rowid keeps it
+    for FeedInfo { uri, title, .. } in &infos {
+        match saved.feeds.get(uri) {
+            // the title is only known once the feed has been fetched
+            Some(old) if old == title || title.is_none() => continue,
+            _ => {}
+        }
+        tx.execute(
+            "INSERT INTO feeds (uri, title) VALUES (?1, ?2)
+             ON CONFLICT (uri) DO UPDATE SET title = COALESCE(excluded.title, title)",
+            params![uri, title],
+        )?;
+    }
+
+    for uri in saved
+        .uris
+        .keys()
+        .filter(|uri| !state.uris.contains_key(*uri))
+    {
+        tx.execute("DELETE FROM episodes WHERE uri = ?1", [uri])?;
+        tx.execute("DELETE FROM positions WHERE uri = ?1", [uri])?;
+    }
+    for (uri, playable) in &state.uris {
+        if saved.uris.get(uri) == Some(playable) {
+            continue;
+        }
+        tx.execute(
+            "INSERT OR REPLACE INTO episodes (uri, title, album, source, length)
+             VALUES (?1, ?2, ?3, ?4, ?5)",
+            params![
+                uri,
+                playable.title,
+                playable.album,
+                playable.source,
+                playable.length
+            ],
+        )?;
+        tx.execute(
+            "INSERT OR REPLACE INTO positions (uri, progress, updated, finished, play_count)
+             VALUES (?1, ?2, ?3, ?4, ?5)",
+            params![
+                uri,
+                playable.progress,
+                playable.updated,
+                playable.finished,
+                playable.play_count
+            ],
+        )?;
+    }
+
+    for (table, old, new) in [
+        ("queue", &saved.queue, &state.queue),
+        ("history", &saved.recent, &state.recent),
+    ] {
+        if old == new {
+            continue;
+        }
+        tx.execute(&format!("DELETE FROM {table}"), [])?;
+        let mut insert = tx.prepare(&format!(
+            "INSERT INTO {table} (position, uri) VALUES (?1, ?2)"
+        ))?;
+        for (position, uri) in new.iter().enumerate() {
+            insert.execute(params![position, uri])?;
+        }
+    }
+
+    let new_episodes = write_new_episodes(tx, &saved.new_episodes, state)?;
+
+    let mut feeds_saved = saved.feeds.clone();
+    feeds_saved.retain(|uri, _| feeds.contains_key(uri));
+    for (uri, title) in feeds {
+        let entry = feeds_saved.entry(uri).or_default();
+        if title.is_some() {
+            *entry = title;
+        }
+    }
+    Ok(Saved {
+        feeds: feeds_saved,
+        uris: state.uris.clone(),
+        queue: state.queue.clone(),
+        recent: state.recent.clone(),
+        new_episodes,
+    })
+}
+
+/// Brings the new episodes from `saved` to those of `state`, returns them.
+fn write_new_episodes(
+    tx: &Transaction,
+    saved: &BTreeSet<String>,
+    state: &State,
+) -> rusqlite::Result<BTreeSet<String>> {
+    let new_episodes = state
+        .new_episodes
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .clone();
+    for key in saved.difference(&new_episodes) {
+        tx.execute("DELETE FROM new_episodes WHERE key = ?1", [key])?;
+    }
+    for key in new_episodes.difference(saved) {
+        tx.execute(
+            "INSERT OR IGNORE INTO new_episodes (key) VALUES (?1)",
+            [key],
+        )?;
+    }
+    Ok(new_episodes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn count(store: &Store, table: &str) -> usize {
+        store
+            .conn
+            .lock()
+            .unwrap()
+            .query_row(&format!("SELECT COUNT(*) FROM {table}"), [], |row| {
+                row.get(0)
+            })
+            .unwrap()
+    }
+
+    #[test]
+    fn save_and_load() {
+        let mut store = Store::open_in_memory().unwrap();
+        let mut state = store.load().unwrap();
+        state
+            .rss_feeds
+            .lock()
+            .unwrap()
+            .push(Arc::new(RssFeed::new("https://a.example/feed".into())));
+        let playable = Playable {
+            title: Some("one".into()),
+            progress: Some(30),
+            updated: Some(1_700_000_000),
+            ..Playable::default()
+        };
+        state.insert_playable("ep1".into(), playable.clone());
+        state.insert_playable("ep2".into(), Playable::default());
+        state.queue("ep1");
+        state.queue("ep2");
+        state.push_recent("ep0");
+        state.new_episodes.lock().unwrap().insert("guid1".into());
+        store.save(&state).unwrap();
+
+        state.uris.remove("ep2");
+        state.pop_queue();
+        store.save(&state).unwrap();
+        assert_eq!(count(&store, "episodes"), 1);
+        assert_eq!(count(&store, "positions"), 1);
+
+        let loaded = store.load().unwrap();
+        assert_eq!(
+            loaded.uris,
+            HashMap::from([("ep1".into(), playable.clone())])
+        );
+        assert_eq!(loaded.queue, ["ep2"]);
+        assert_eq!(loaded.recent, ["ep0"]);
+        assert!(loaded.new_episodes.lock().unwrap().contains("guid1"));
+        assert_eq!(loaded.feed_infos()[0].uri, "https://a.example/feed");
+
+        state.new_episodes.lock().unwrap().insert("guid2".into());
+        store.save_new_episodes(&state).unwrap();
+        assert_eq!(count(&store, "new_episodes"), 2);
+
+        state.rss_feeds.lock().unwrap().clear();
+        state.new_episodes.lock().unwrap().clear();
+        store.save(&state).unwrap();
+        assert_eq!(count(&store, "feeds"), 0);
+        assert_eq!(count(&store, "new_episodes"), 0);
+
+        let session = Session {
+            uri: "ep1".into(),
+            feed: None,
+            started: 1_700_000_000,
+            stopped: 1_700_000_030,
+            from: Some(0),
+            to: Some(30),
+        };
+        store.record(&session).unwrap();
+        assert_eq!(store.sessions().unwrap(), [session]);
+
+        let unsent = vec![(
+            "desktop:7777".to_string(),
+            UpdateArgs("ep1".into(), playable),
+        )];
+        store.set_unsent(&unsent).unwrap();
+        assert_eq!(store.unsent().unwrap(), unsent);
+        store.set_unsent(&[]).unwrap();
+        assert!(store.unsent().unwrap().is_empty());
+    }
+
+    #[test]
+    fn import_json() {
+        let dir = std::env::temp_dir().join(format!("podaemon-store-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let json = dir.join("state");
+        fs::write(
+            &json,
+            r#"{"rss_feeds": [{"uri": "https://a.example/feed"}],
+                "uris": {"ep1": {"progress": 10, "updated": 5}},
+                "queue": ["ep1"], "recent": []}"#,
+        )
+        .unwrap();
+
+        let mut store = Store::open(&dir.join(DB)).unwrap();
+        assert!(!store.imported().unwrap());
+        store.import(&State::from_disc2(&json).unwrap()).unwrap();
+        assert!(store.imported().unwrap());
+
+        let mut store = Store::open(&dir.join(DB)).unwrap();
+        let state = store.load().unwrap();
+        assert_eq!(state.uris["ep1"].progress, Some(10));
+        assert_eq!(state.queue, ["ep1"]);
+        assert_eq!(state.feed_infos().len(), 1);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    /// What `state.json` and `store-v1.sql` hold.
+    fn assert_show(state: &State) {
+        let feeds: Vec<String> = state.feed_infos().into_iter().map(|f| f.uri).collect();
+        assert_eq!(
+            feeds,
+            [
+                "https://feeds.example.com/show.rss",
+                "https://other.example.org/feed"
+            ]
+        );
+        let ep12 = &state.uris["https://cdn.example.com/show/12.mp3"];
+        assert_eq!(
+            (ep12.progress, ep12.length, ep12.updated),
+            (Some(1830), Some(3600), Some(1_700_000_000))
+        );
+        assert_eq!(
+            ep12.source.as_deref(),
+            Some("https://feeds.example.com/show.rss")
+        );
+        let ep13 = &state.uris["https://cdn.example.com/show/13.mp3"];
+        assert_eq!(ep13.title.as_deref(), Some("Episode 13"));
+        assert_eq!(ep13.progress, None);
+        assert_eq!(state.queue, ["https://cdn.example.com/show/13.mp3"]);
+        assert_eq!(state.recent, ["https://cdn.example.com/show/12.mp3"]);
+    }
+
+    #[test]
+    fn load_past_versions() {
+        // JSON state files from before the database, oldest first
+        let json: State =
+            serde_json::from_str(include_str!("../../tests/fixtures/state-uris-only.json"))
+                .unwrap();
+        let mut store = Store::open_in_memory().unwrap();
+        store.import(&json).unwrap();
+        let state = store.load().unwrap();
+        assert_eq!(state.uris["file:///music/talk.mp3"].progress, Some(754));
+        assert!(state.queue.is_empty() && state.feed_infos().is_empty());
+
+        let json: State =
+            serde_json::from_str(include_str!("../../tests/fixtures/state.json")).unwrap();
+        let mut store = Store::open_in_memory().unwrap();
+        store.import(&json).unwrap();
+        assert_show(&store.load().unwrap());
+
+        // databases of every schema version
+        for (dump, play_count, sessions, new_episodes, unsent) in [
+            (
+                include_str!("../../tests/fixtures/store-v1.sql"),
+                0,
+                0,
+                0,
+                0,
+            ),
+            (
+                include_str!("../../tests/fixtures/store-v2.sql"),
+                1,
+                0,
+                0,
+                0,
+            ),
+            (
+                include_str!("../../tests/fixtures/store-v3.sql"),
+                1,
+                2,
+                0,
+                0,
+            ),
+            (
+                include_str!("../../tests/fixtures/store-v4.sql"),
+                1,
+                2,
+                1,
+                0,
+            ),
+            (
+                include_str!("../../tests/fixtures/store-v5.sql"),
+                1,
+                2,
+                1,
+                1,
+            ),
+        ] {
+            let conn = Connection::open_in_memory().unwrap();
+            conn.execute_batch(dump).unwrap();
+            let mut store = Store::init(conn).unwrap();
+            assert!(store.imported().unwrap());
+            let state = store.load().unwrap();
+            assert_show(&state);
+            let ep12 = &state.uris["https://cdn.example.com/show/12.mp3"];
+            assert_eq!(ep12.play_count, play_count);
+            assert_eq!(store.sessions().unwrap().len(), sessions);
+            assert_eq!(state.new_episodes.lock().unwrap().len(), new_episodes);
+            assert_eq!(store.unsent().unwrap().len(), unsent);
+        }
+    }
+
+    #[test]
+    fn refuse_newer_version() {
+        let conn = Connection::open_in_memory().unwrap();
+        conn.pragma_update(None, "user_version", VERSION + 1)
+            .unwrap();
+        let err = Store::init(conn).unwrap_err();
+        assert!(err.is::<NewerVersion>());
+        assert!(err.to_string().contains("refusing"), "{err}");
+    }
+
+    #[test]
+    fn recover_from_backup() {
+        let dir = std::env::temp_dir().join(format!("podaemon-recover-{}", std::process::id()));
+        let path = dir.join(DB);
+        let mut store = Store::open(&path).unwrap();
+        let mut state = store.load().unwrap();
+        state.insert_playable("ep1".into(), Playable::default());
+        store.save(&state).unwrap();
+        store.back_up(&path);
+        drop(store);
+
+        fs::write(&path, "not a database").unwrap();
+        let err = Store::open(&path).unwrap_err();
+        let (mut store, warning) = Store::recover(&path, &*err).unwrap();
+        assert!(warning.contains("restored"), "{warning}");
+        assert!(store.load().unwrap().uris.contains_key("ep1"));
+        drop(store);
+
+        // without backups there is nothing but an empty database
+        for n in 1..=data::backups() {
+            let _ = fs::remove_file(data::backup_path(&path, n));
+        }
+        fs::write(&path, "not a database").unwrap();
+        let err = Store::open(&path).unwrap_err();
+        let (mut store, warning) = Store::recover(&path, &*err).unwrap();
+        assert!(warning.contains("started an empty one"), "{warning}");
+        assert!(store.load().unwrap().uris.is_empty());
+        let moved = fs::read_dir(&dir)
+            .unwrap()
+            .filter(|entry| {
+                let name = entry.as_ref().unwrap().file_name();
+                name.to_string_lossy().starts_with("podaemon.db.corrupt-")
+            })
+            .count();
+        assert_eq!(moved, 2);
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -2,7 +2,10 @@ mod episodes_tab;
 mod feed_tab;
 pub mod log;
 mod player_tab;
+mod stats_tab;
 
 #[macro_use]
 pub mod macros;
 pub mod interface;
+pub mod remote;
+pub mod view;
@@ -7,14 +7,11 @@ use ratatui::{
     Frame,
 };
 
-use crate::player::{
-    state::{Episode, Playable},
-    Player,
-};
+use crate::player::state::{item_key, Episode, Playable};
 
-use super::interface::UiState;
+use super::{interface::UiState, view::View};
 
-pub fn draw_episodes_tab<B: Backend>(f: &mut Frame<B>, player: &Player, ui_state: &mut UiState) {
+pub fn draw_episodes_tab<B: Backend>(f: &mut Frame<B>, player: &impl View, ui_state: &mut UiState) {
     let chunks = Layout::default()
         .direction(Direction::Vertical)
         .margin(0)
@@ -35,7 +32,10 @@ pub fn draw_episodes_tab<B: Backend>(f: &mut Frame<B>, player: &Player, ui_state
     let half_height = (tbl_height - 2) / 2;
     let first = ui_state.get_cursor_pos().saturating_sub(half_height.into());
 
+    let new_episodes = player.new_episodes();
+    let is_new = |item| item_key(item).is_some_and(|key| new_episodes.contains(key));
     if let Ok(episodes) = ui_state.episodes.lock() {
+        let new_count = episodes.iter().filter(|ep| is_new(&ep.item)).count();
         let episodes: Vec<Row> = episodes
             .iter()
             .enumerate()
@@ -57,15 +57,17 @@ pub fn draw_episodes_tab<B: Backend>(f: &mut Frame<B>, player: &Player, ui_state
                         .and_then(Result::ok)
                         .map(|dt| dt.format("%Y-%m-%d").to_string());
 
-                    let progress = item
+                    let status = item
                         .enclosure()
-                        .and_then(|e| player.state.uris.get(&e.url))
-                        .map(Playable::progress_string);
+                        .and_then(|e| player.playable(&e.url))
+                        .map(Playable::status_string)
+                        .filter(|status| !status.is_empty());
+                    let status = status.or_else(|| is_new(item).then(|| "new".into()));
 
                     let item = Row::new(vec![
                         // Cell::from(i.to_string()),
                         Cell::from(date.unwrap_or_default()),
-                        Cell::from(progress.unwrap_or_default()),
+                        Cell::from(status.unwrap_or_default()),
                         Cell::from(channel_title.to_string()),
                         Cell::from(pod_title.to_string()),
                     ]);
@@ -80,12 +82,16 @@ pub fn draw_episodes_tab<B: Backend>(f: &mut Frame<B>, player: &Player, ui_state
         let constraints = [
             // Constraint::Length(3),
             Constraint::Length(10),
-            Constraint::Length(5),
+            Constraint::Length(6),
             Constraint::Length(18),
             Constraint::Length(chunks[2].width),
         ];
+        let title = match new_count {
+            0 => "Episodes".to_string(),
+            n => format!("Episodes ({n} new)"),
+        };
         let tbl = Table::new(episodes)
-            .block(Block::default().borders(Borders::ALL).title("Episodes"))
+            .block(Block::default().borders(Borders::ALL).title(title))
             .header(
                 // Row::new(vec!["i", " State ", "Date", "Podcast", "Episode"])
                 Row::new(vec!["Date", "State", "Podcast", "Episode"])
@@ -1,15 +1,15 @@
 use super::interface::{last_n, UiState};
-use crate::player::Player;
+use super::view::View;
 use ratatui::{
     backend::Backend,
     layout::{Constraint, Direction, Layout},
     style::{Color, Style},
-    text::{Span, Line},
+    text::{Line, Span},
     widgets::{Block, Borders, List, ListItem, Paragraph},
     Frame,
 };
 
-pub fn draw_feed_tab<B: Backend>(f: &mut Frame<B>, player: &Player, ui_state: &mut UiState) {
+pub fn draw_feed_tab<B: Backend>(f: &mut Frame<B>, player: &impl View, ui_state: &mut UiState) {
     let chunks = Layout::default()
         .direction(Direction::Vertical)
         .margin(0)
@@ -26,41 +26,55 @@ pub fn draw_feed_tab<B: Backend>(f: &mut Frame<B>, player: &Player, ui_state: &m
     let half_height = (chunks[1].height - 2) / 2;
     let first = ui_state.get_cursor_pos().saturating_sub(half_height.into());
 
-    if let Ok(rss_feeds) = player.state.rss_feeds.lock() {
-        let feeds: Vec<ListItem> = rss_feeds
-            .iter()
-            .enumerate()
-            .skip(first)
-            .take(chunks[1].height as usize)
-            .map(|(i, feed)| {
-                let channel_guard = feed.channel.read();
-                let text = if let Ok(Some(channel)) = channel_guard.as_deref() {
-                    &channel.title
-                } else {
-                    &feed.uri
-                };
+    let feeds: Vec<ListItem> = player
+        .feeds()
+        .iter()
+        .enumerate()
+        .skip(first)
+        .take(chunks[1].height as usize)
+        .map(|(i, feed)| {
+            let text = feed.title.as_ref().unwrap_or(&feed.uri);
+            let mut suffix = String::new();
+            if feed.new_episodes > 0 {
+                suffix.push_str(&format!(" ({} new)", feed.new_episodes));
+            }
+            if feed.refreshing {
+                suffix.push_str(" refreshing…");
+            }
 
-                let content = vec![Line::from(Span::raw(format!(
-                    "{}: {}",
-                    i,
-                    last_n(text, chunks[1].width.saturating_sub(5))
-                )))];
-                let item = ListItem::new(content);
-                if ui_state.get_cursor_pos() == i {
-                    item.style(Style::default().fg(Color::Black).bg(Color::White))
-                } else {
-                    item
-                }
-            })
-            .collect();
-        let feeds = List::new(feeds).block(Block::default().borders(Borders::ALL).title("Feeds"));
-        f.render_widget(feeds, chunks[1]);
+            let content = vec![Line::from(Span::raw(format!(
+                "{}: {}{suffix}",
+                i,
+                last_n(
+                    text,
+                    chunks[1]
+                        .width
+                        .saturating_sub(5 + suffix.chars().count() as u16)
+                )
+            )))];
+            let item = ListItem::new(content);
+            if ui_state.get_cursor_pos() == i {
+                item.style(Style::default().fg(Color::Black).bg(Color::White))
+            } else {
+                item
+            }
+        })
+        .collect();
+    let feeds = List::new(feeds).block(Block::default().borders(Borders::ALL).title("Feeds"));
+    f.render_widget(feeds, chunks[1]);
 
-        if let Some((prompt, _, _, _)) = &ui_state.file_prompt {
-            let input = Paragraph::new(format!(": {prompt}"))
-                .style(Style::default())
-                .block(Block::default());
-            f.render_widget(input, chunks[2]);
-        }
+    if let Some(feed) = &ui_state.unsubscribe {
+        let name = feed.title.as_ref().unwrap_or(&feed.uri);
+        let confirm = Paragraph::new(format!(
+            "unsubscribe from {name}? y: yes, p: yes and forget its episodes, any other key: no"
+        ))
+        .style(Style::default().fg(Color::Yellow));
+        f.render_widget(confirm, chunks[2]);
+    } else if let Some((prompt, _, _, _)) = &ui_state.file_prompt {
+        let label = ui_state.feed_prompt.label();
+        let input = Paragraph::new(format!("{label}: {prompt}"))
+            .style(Style::default())
+            .block(Block::default());
+        f.render_widget(input, chunks[2]);
     }
 }
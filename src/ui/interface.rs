@@ -7,19 +7,21 @@ use std::{
 };
 
 use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
-use tokio::sync::mpsc::Sender;
 use ratatui::{
     backend::CrosstermBackend,
     layout::{Constraint, Layout},
     style::{Color, Style},
-    text::{Span, Line},
-    widgets::{Block, Borders, Tabs},
+    text::{Line, Span},
+    widgets::{Block, Borders, Paragraph, Tabs},
     Terminal,
 };
+use serde_json::Value;
+use tokio::sync::mpsc::Sender;
 
 use crate::player::{
-    state::{get_time, Episode, Playable},
-    Cmd, Player,
+    query::FeedInfo,
+    state::{Episode, EpisodeStatus, Playable},
+    Cmd, Request, UpdateArgs,
 };
 
 use super::{
@@ -27,38 +29,82 @@ use super::{
     feed_tab::draw_feed_tab,
     log::{self, draw_event_log_tab},
     player_tab::draw_player_tab,
+    stats_tab::draw_stats_tab,
+    view::View,
 };
-const TAB_TITLES: &[&str] = &["Player", "Episodes", "Feeds", "Log"];
+const TAB_TITLES: &[&str] = &["Player", "Episodes", "Feeds", "Log", "Stats"];
+
+/// What the file prompt of the Feeds tab is asking for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FeedPrompt {
+    Subscribe,
+    ImportOpml,
+    ExportOpml,
+}
+
+impl FeedPrompt {
+    pub fn label(self) -> &'static str {
+        match self {
+            FeedPrompt::Subscribe => "",
+            FeedPrompt::ImportOpml => "import opml ",
+            FeedPrompt::ExportOpml => "export opml ",
+        }
+    }
+}
 
 pub struct UiState {
     pub tab_index: usize,
     cursor_position: [usize; TAB_TITLES.len()],
     pub file_prompt: Option<(String, bool, Option<usize>, Vec<String>)>,
     pub prompt: Option<String>,
+    pub feed_prompt: FeedPrompt,
+    /// Feed to unsubscribe from once the user confirms.
+    pub unsubscribe: Option<FeedInfo>,
     pub hit_number: isize,
     pub vscroll: u16,
     key_hist: Vec<KeyEvent>,
     // pub episodes: Arc<Mutex<Vec<(String, Item)>>>,
     pub episodes: Arc<Mutex<BTreeSet<Episode>>>,
-    tx: Sender<Cmd>,
+    tx: Sender<Request>,
 }
 impl UiState {
     async fn send_cmd(&self, cmd: Cmd) {
-        if let Err(err) = self.tx.send(cmd).await {
+        if let Err(err) = self.tx.send(cmd.into()).await {
             logln!("{err}");
         }
     }
 
-    pub fn new(tx: Sender<Cmd>) -> UiState {
+    /// Writes the subscriptions to `path` once the player answers.
+    async fn export_opml(&self, path: String) {
+        let (request, rx) = Request::new(Cmd::ExportOpml);
+        if let Err(err) = self.tx.send(request).await {
+            logln!("{err}");
+            return;
+        }
+        tokio::spawn(async move {
+            match rx.await {
+                Ok(Ok(Some(Value::String(xml)))) => match std::fs::write(&path, xml) {
+                    Ok(()) => logln!("exported feeds to {path}"),
+                    Err(err) => logln!("{path}: {err}"),
+                },
+                Ok(Err(err)) => logln!("failed to export opml: {err}"),
+                _ => logln!("failed to export opml: unexpected answer"),
+            }
+        });
+    }
+
+    pub fn new(tx: Sender<Request>, episodes: Arc<Mutex<BTreeSet<Episode>>>) -> UiState {
         Self {
             tab_index: 0,
             hit_number: 0,
             cursor_position: [0; TAB_TITLES.len()],
             file_prompt: None,
             prompt: None,
+            feed_prompt: FeedPrompt::Subscribe,
+            unsubscribe: None,
             vscroll: 0,
             key_hist: Vec::new(),
-            episodes: Arc::new(Mutex::new(BTreeSet::new())),
+            episodes,
             tx,
         }
     }
@@ -103,9 +149,9 @@ impl UiState {
         self.cursor_position[self.tab_index]
     }
 
-    fn get_cursor_bound(&self, player: &Player) -> usize {
+    fn get_cursor_bound(&self, player: &impl View) -> usize {
         let bound = match self.tab_index {
-            0 => player.state.recent.len() + player.state.queue.len(),
+            0 => player.recent().len() + player.queue().len(),
             1 => {
                 if let Ok(eps) = self.episodes.lock() {
                     eps.len()
@@ -113,12 +159,7 @@ impl UiState {
                     usize::MAX
                 }
             }
-            2 => player
-                .state
-                .rss_feeds
-                .lock()
-                .map(|v| v.len())
-                .unwrap_or(usize::MAX),
+            2 => player.feeds().len(),
             3 => log::get_cursor_bound(),
             _ => usize::MAX,
         };
@@ -206,17 +247,23 @@ impl UiState {
                             uri.push_str(s);
                             mem::swap(s, &mut uri);
                         };
-                        if let Err(err) = self.tx.send(Cmd::Queue(mem::take(s))).await {
+                        if let Err(err) = self.tx.send(Cmd::Queue(mem::take(s)).into()).await {
                             logln!("{err}");
                         }
                         self.file_prompt = None;
                     } else if self.tab_index == 2 {
-                        if let Err(err) = self
-                            .tx
-                            .send(Cmd::Subscribe(mem::replace(s, "".to_string())))
-                            .await
-                        {
-                            logln!("Subscribe error: {err}");
+                        let arg = mem::take(s);
+                        if self.feed_prompt != FeedPrompt::Subscribe {
+                            self.file_prompt = None;
+                        }
+                        // files are on this side, the daemon may be elsewhere
+                        match self.feed_prompt {
+                            FeedPrompt::Subscribe => self.send_cmd(Cmd::Subscribe(arg)).await,
+                            FeedPrompt::ImportOpml => match std::fs::read_to_string(&arg) {
+                                Ok(xml) => self.send_cmd(Cmd::ImportOpml(xml)).await,
+                                Err(err) => logln!("{arg}: {err}"),
+                            },
+                            FeedPrompt::ExportOpml => self.export_opml(arg).await,
                         }
                     }
                 }
@@ -226,14 +273,20 @@ impl UiState {
         }
     }
 
-    pub async fn update(&mut self, event: UiUpdate, player: &mut Player) {
+    pub async fn update(&mut self, event: UiUpdate, player: &impl View) {
         match event {
             UiUpdate::KeyEvent(
                 event @ KeyEvent {
                     code, modifiers, ..
                 },
             ) => {
-                if self.file_prompt.is_some() {
+                if let Some(feed) = self.unsubscribe.take() {
+                    match code {
+                        KeyCode::Char('y') => self.send_cmd(Cmd::Unsubscribe(feed.uri)).await,
+                        KeyCode::Char('p') => self.send_cmd(Cmd::Purge(feed.uri)).await,
+                        _ => {}
+                    }
+                } else if self.file_prompt.is_some() {
                     self.file_prompt_update(code).await;
                 } else if self.prompt.is_some() {
                     self.search_update(code);
@@ -285,6 +338,7 @@ impl UiState {
                                     String::new()
                                 };
 
+                                self.feed_prompt = FeedPrompt::Subscribe;
                                 if self.file_prompt.is_none() {
                                     self.file_prompt = Some((init, true, None, Vec::new()));
                                 } else {
@@ -295,6 +349,60 @@ impl UiState {
                             }
                         }
 
+                        Char('i') | Char('e') if self.tab_index == 2 => {
+                            self.feed_prompt = if code == Char('i') {
+                                FeedPrompt::ImportOpml
+                            } else {
+                                FeedPrompt::ExportOpml
+                            };
+                            self.file_prompt = Some((String::new(), true, None, Vec::new()));
+                        }
+
+                        Char('r') | Char('R') if self.tab_index == 2 => {
+                            // the selected feed, all of them with shift
+                            let uri = if code == Char('r') {
+                                let feeds = player.feeds();
+                                let Some(feed) = feeds.get(self.get_cursor_pos()) else {
+                                    return;
+                                };
+                                Some(feed.uri.clone())
+                            } else {
+                                None
+                            };
+                            self.send_cmd(Cmd::Refresh(uri)).await;
+                        }
+
+                        Char('n') | Char('N') if self.tab_index == 2 => {
+                            // seen: the selected feed, all of them with shift
+                            let uri = if code == Char('n') {
+                                let feeds = player.feeds();
+                                let Some(feed) = feeds.get(self.get_cursor_pos()) else {
+                                    return;
+                                };
+                                Some(feed.uri.clone())
+                            } else {
+                                None
+                            };
+                            self.send_cmd(Cmd::MarkSeen(uri)).await;
+                        }
+
+                        Char('m') if self.tab_index == 1 => {
+                            let url = self.episodes.lock().ok().and_then(|eps| {
+                                let episode = eps.iter().nth(self.get_cursor_pos())?;
+                                Some(episode.item.enclosure()?.url.clone())
+                            });
+                            if let Some(url) = url {
+                                let played = player.playable(&url).map(Playable::status)
+                                    == Some(EpisodeStatus::Played);
+                                let cmd = if played {
+                                    Cmd::MarkUnplayed(url)
+                                } else {
+                                    Cmd::MarkPlayed(url)
+                                };
+                                self.send_cmd(cmd).await;
+                            }
+                        }
+
                         KeyCode::Char('/') => {
                             if self.tab_index == 1 {
                                 self.prompt = Some("".to_string());
@@ -310,15 +418,19 @@ impl UiState {
                                 self.cursor_position[self.tab_index] = new;
                             } else if self.tab_index == 0 {
                                 let cpos = self.get_cursor_pos();
-                                let recent_size = player.state.recent.len();
+                                let recent_size = player.recent().len();
                                 let cmd = if cpos < recent_size {
                                     Cmd::DeleteRecent(recent_size - cpos - 1)
                                 } else {
                                     Cmd::DeleteQueue(cpos - recent_size)
                                 };
-                                if let Err(err) = self.tx.send(cmd).await {
+                                if let Err(err) = self.tx.send(cmd.into()).await {
                                     logln!("Failed to send delete: {err}");
                                 }
+                            } else if self.tab_index == 2 {
+                                if let Some(feed) = player.feeds().get(self.get_cursor_pos()) {
+                                    self.unsubscribe = Some(feed.clone());
+                                }
                             }
                         }
 
@@ -375,19 +487,14 @@ impl UiState {
                                 });
 
                                 if let Some((chan_title, title, url, source)) = info {
-                                    let url2 = url.clone();
-                                    let pos = player.state.uris.get(&url);
                                     let playable = Playable {
                                         title,
                                         album: Some(chan_title),
-                                        progress: pos.and_then(|x| x.progress),
                                         source,
-                                        updated: Some(get_time()),
-                                        length: None,
+                                        ..Playable::default()
                                     };
-                                    player.state.insert_playable(url, playable);
-
-                                    if let Err(err) = self.tx.send(Cmd::Queue(url2)).await {
+                                    let cmd = Cmd::QueueEpisode(UpdateArgs(url, playable));
+                                    if let Err(err) = self.tx.send(cmd.into()).await {
                                         logln!("failed to queue: {err}");
                                     }
                                 };
@@ -408,7 +515,7 @@ pub enum UiUpdate {
 
 pub fn draw_ui(
     terminal: &mut Terminal<CrosstermBackend<Stdout>>,
-    player: &mut Player,
+    player: &impl View,
     ui_state: &mut UiState,
 ) {
     let _ = terminal.draw(|f| {
@@ -427,11 +534,23 @@ pub fn draw_ui(
             .highlight_style(Style::default().fg(Color::Yellow));
         f.render_widget(tabs, chunks[0]);
 
+        // next to the tab titles, the full text is in the log
+        if let Some(warning) = player.warning() {
+            let titles_width = TAB_TITLES.iter().map(|t| t.len() as u16 + 3).sum::<u16>() + 1;
+            let mut area = chunks[0];
+            area.x += titles_width.min(area.width);
+            area.width -= titles_width.min(area.width);
+            area.height = 1;
+            let warning = Paragraph::new(warning).style(Style::default().fg(Color::Red));
+            f.render_widget(warning, area);
+        }
+
         match ui_state.tab_index {
             0 => draw_player_tab(f, player, ui_state),
             1 => draw_episodes_tab(f, player, ui_state),
             2 => draw_feed_tab(f, player, ui_state),
             3 => draw_event_log_tab(f, ui_state),
+            4 => draw_stats_tab(f, player),
             _ => (),
         }
     });
@@ -1,4 +1,13 @@
-use std::{collections::VecDeque, fmt, fs::File, io::Write, sync::Mutex};
+use std::{
+    collections::VecDeque,
+    fmt,
+    fs::File,
+    io::Write,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Mutex,
+    },
+};
 
 use once_cell::sync::Lazy;
 use ratatui::{
@@ -17,6 +26,13 @@ pub static LOG: Lazy<Mutex<VecDeque<String>>> = Lazy::new(|| {
     Mutex::new(msgs)
 });
 
+static STDERR: AtomicBool = AtomicBool::new(false);
+
+/// Echo log lines to stderr, for when there is no log tab to read them in.
+pub fn log_to_stderr() {
+    STDERR.store(true, Ordering::Relaxed);
+}
+
 pub fn _log(msg: fmt::Arguments) {
     let time = chrono::Local::now().format("%m-%d %H:%M:%S%.3f");
     let mut log_file = std::env::var("LOG_FILE")
@@ -24,6 +40,9 @@ pub fn _log(msg: fmt::Arguments) {
         .and_then(|name| File::options().create(true).append(true).open(name).ok());
     if let Ok(mut log) = LOG.lock() {
         let msg = format!("{time}: {}", msg);
+        if STDERR.load(Ordering::Relaxed) {
+            eprintln!("{msg}");
+        }
         if let Some(Err(err)) = log_file.as_mut().map(|log_file| {
             log_file
                 .write(msg.as_bytes())
@@ -4,17 +4,17 @@ use ratatui::{
     backend::Backend,
     layout::{Constraint, Direction, Layout, Rect},
     style::{Color, Style},
-    text::{Span, Line},
+    text::{Line, Span},
     widgets::{Block, Borders, Cell, List, ListItem, Paragraph, Row, Table},
     Frame,
 };
 
-use crate::{dir::children, player::Player};
+use crate::dir::children;
 
 use super::interface::{last_n, UiState};
-use gstreamer::State;
+use super::view::View;
 
-pub fn draw_player_tab<B: Backend>(f: &mut Frame<B>, player: &Player, ui_state: &mut UiState) {
+pub fn draw_player_tab<B: Backend>(f: &mut Frame<B>, player: &impl View, ui_state: &mut UiState) {
     let chunks = Layout::default()
         .direction(Direction::Vertical)
         .margin(0)
@@ -41,30 +41,26 @@ pub fn draw_player_tab<B: Backend>(f: &mut Frame<B>, player: &Player, ui_state:
 }
 
 const RECENT_SIZE: usize = 10;
-fn draw_recents<B: Backend>(f: &mut Frame<B>, chunk: Rect, ui_state: &UiState, player: &Player) {
-    let recent_len = player.state.recent.len();
+fn draw_recents<B: Backend>(f: &mut Frame<B>, chunk: Rect, ui_state: &UiState, player: &impl View) {
+    let recent_len = player.recent().len();
     let to_skip = recent_len
         .saturating_sub(RECENT_SIZE)
         .saturating_sub(ui_state.get_cursor_pos());
     let recent: Vec<Row> = player
-        .state
-        .recent
+        .recent()
         .iter()
         .enumerate()
         .skip(to_skip)
         .take(RECENT_SIZE)
         .map(|(i, uri)| {
-            let name = if let Some(name) = player.state.uris.get(uri).and_then(|p| p.title.as_ref())
-            {
+            let name = if let Some(name) = player.playable(uri).and_then(|p| p.title.as_ref()) {
                 name
             } else {
                 uri
             };
 
             let chan_title = player
-                .state
-                .uris
-                .get(uri)
+                .playable(uri)
                 .and_then(|p| p.album.as_ref())
                 .map(|s| s.as_str())
                 .unwrap_or("");
@@ -136,7 +132,7 @@ fn draw_playlist<B: Backend>(
     f: &mut Frame<B>,
     chunk: Rect,
     ui_state: &mut UiState,
-    player: &Player,
+    player: &impl View,
 ) {
     //                                   2 for border, 1 for header
     ui_state.vscroll = chunk.height.saturating_sub(2 + 1);
@@ -148,28 +144,24 @@ fn draw_playlist<B: Backend>(
         first
     };
     let playlist: Vec<Row> = player
-        .state
-        .queue
+        .queue()
         .iter()
         .enumerate()
         .skip(to_skip)
         .map(|(i, uri)| {
-            let name = if let Some(name) = player.state.uris.get(uri).and_then(|p| p.title.as_ref())
-            {
+            let name = if let Some(name) = player.playable(uri).and_then(|p| p.title.as_ref()) {
                 name
             } else {
                 uri
             };
 
             let chan_title = player
-                .state
-                .uris
-                .get(uri)
+                .playable(uri)
                 .and_then(|p| p.album.as_ref())
                 .map(|s| s.as_str())
                 .unwrap_or("");
 
-            let r_len = player.state.recent.len();
+            let r_len = player.recent().len();
             let item = Row::new(vec![
                 Cell::from(i.to_string()),
                 Cell::from(chan_title.to_string()),
@@ -199,14 +191,14 @@ fn draw_playlist<B: Backend>(
     f.render_widget(playlist, chunk);
 }
 
-fn draw_current_info<B: Backend>(f: &mut Frame<B>, chunk: Rect, player: &Player) {
+fn draw_current_info<B: Backend>(f: &mut Frame<B>, chunk: Rect, player: &impl View) {
     let position = player
-        .query_position()
+        .position()
         .map(|time| format!("{:.0}", time))
         .unwrap_or_else(|| "n\\a".to_string());
 
     let duration = player
-        .duration
+        .duration()
         .map(|time| format!("{:.0}", time))
         .unwrap_or_else(|| "n\\a".to_string());
 
@@ -217,11 +209,9 @@ fn draw_current_info<B: Backend>(f: &mut Frame<B>, chunk: Rect, player: &Player)
         chunk.width as usize - p_length
     };
 
-    let name = if let Some(uri) = &player.current_uri {
+    let name = if let Some(uri) = player.current_uri() {
         if let Some(name) = player
-            .state
-            .uris
-            .get(uri)
+            .playable(uri)
             .and_then(|playable| playable.title.as_ref())
         {
             name
@@ -235,20 +225,9 @@ fn draw_current_info<B: Backend>(f: &mut Frame<B>, chunk: Rect, player: &Player)
     let text = format!("{name} {position} / {duration}");
     let progress = Paragraph::new(text).block(
         Block::default()
-            .title(state_to_str(player.play_state))
+            .title(player.play_state())
             .borders(Borders::ALL)
             .style(Style::default().fg(Color::White)),
     );
     f.render_widget(progress, chunk);
 }
-
-const fn state_to_str(state: State) -> &'static str {
-    match state {
-        State::VoidPending => "Void",
-        State::Null => "Null",
-        State::Ready => "Ready",
-        State::Paused => "Paused",
-        State::Playing => "Playing",
-        _ => "Unknown",
-    }
-}
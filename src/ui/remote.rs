@@ -0,0 +1,297 @@
+//! The terminal interface attached to a daemon over its control connection.
+//! It renders from a [`Snapshot`] that is fetched again whenever the pushed
+//! events say that something changed. Quitting detaches, the daemon keeps
+//! playing.
+
+use std::{
+    collections::{BTreeSet, HashMap, VecDeque},
+    io::stdout,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use gstreamer::ClockTime;
+use ratatui::{backend::CrosstermBackend, Terminal};
+use serde_json::Value;
+use tokio::{
+    io::{AsyncBufReadExt, AsyncWrite, AsyncWriteExt, BufReader},
+    select,
+    sync::mpsc::{self, Receiver},
+    time,
+};
+
+use crate::{
+    control::{
+        self,
+        rpc::{RpcRequest, RpcResponse},
+    },
+    events::Event,
+    player::{
+        query::{FeedInfo, Snapshot},
+        state::{Episode, Playable},
+        stats::Stats,
+        Cmd, Reply, Request, Response,
+    },
+};
+
+use super::{
+    interface::{draw_ui, UiState, UiUpdate},
+    view::View,
+};
+
+pub struct Remote {
+    snapshot: Snapshot,
+}
+
+impl View for Remote {
+    fn queue(&self) -> &VecDeque<String> {
+        &self.snapshot.queue
+    }
+
+    fn recent(&self) -> &VecDeque<String> {
+        &self.snapshot.recent
+    }
+
+    fn playable(&self, uri: &str) -> Option<&Playable> {
+        self.snapshot.uris.get(uri)
+    }
+
+    fn current_uri(&self) -> Option<&str> {
+        self.snapshot.status.uri.as_deref()
+    }
+
+    fn position(&self) -> Option<ClockTime> {
+        self.snapshot.status.position.map(ClockTime::from_seconds)
+    }
+
+    fn duration(&self) -> Option<ClockTime> {
+        self.snapshot.status.duration.map(ClockTime::from_seconds)
+    }
+
+    fn play_state(&self) -> &str {
+        &self.snapshot.status.state
+    }
+
+    fn feeds(&self) -> &[FeedInfo] {
+        &self.snapshot.feeds
+    }
+
+    fn warning(&self) -> Option<&str> {
+        self.snapshot.status.warning.as_deref()
+    }
+
+    fn stats(&self) -> &Stats {
+        &self.snapshot.stats
+    }
+
+    fn new_episodes(&self) -> &BTreeSet<String> {
+        &self.snapshot.new_episodes
+    }
+}
+
+/// What to do with the response to a request we sent.
+enum Pending {
+    Reply(Reply),
+    Snapshot,
+    Episodes,
+}
+
+/// Writing half of the connection, keeps track of the requests in flight.
+struct Client<W> {
+    writer: W,
+    next_id: u64,
+    pending: HashMap<u64, Pending>,
+    /// Something changed while a snapshot was already on its way.
+    stale: bool,
+}
+
+impl<W: AsyncWrite + Unpin> Client<W> {
+    async fn send(&mut self, cmd: &Cmd, pending: Pending) -> Result<(), String> {
+        self.next_id += 1;
+        let request =
+            RpcRequest::new(cmd, Some(self.next_id.into())).map_err(|err| err.to_string())?;
+        let mut line = serde_json::to_string(&request).map_err(|err| err.to_string())?;
+        line.push('\n');
+        self.writer
+            .write_all(line.as_bytes())
+            .await
+            .map_err(|err| format!("connection error: {err}"))?;
+        self.pending.insert(self.next_id, pending);
+        Ok(())
+    }
+
+    async fn refresh(&mut self) -> Result<(), String> {
+        if self
+            .pending
+            .values()
+            .any(|p| matches!(p, Pending::Snapshot))
+        {
+            self.stale = true;
+            return Ok(());
+        }
+        self.send(&Cmd::Snapshot, Pending::Snapshot).await
+    }
+}
+
+/// Runs the interface against the daemon at `host`, or behind the unix socket,
+/// until the user quits or the daemon goes away.
+pub async fn attach(
+    host: Option<&str>,
+    token: Option<&str>,
+    mut ui_rx: Receiver<UiUpdate>,
+) -> Result<(), String> {
+    let stream = control::connect(host, token).await?;
+    let (reader, writer) = tokio::io::split(stream);
+    let mut lines = BufReader::new(reader).lines();
+    let mut client = Client {
+        writer,
+        next_id: 0,
+        pending: HashMap::new(),
+        stale: false,
+    };
+    let io_err = |err| format!("connection error: {err}");
+
+    client.writer.write_all(b"watch\n").await.map_err(io_err)?;
+    match lines.next_line().await.map_err(io_err)? {
+        Some(ack) if ack == "ok" => {}
+        Some(err) => return Err(err),
+        None => return Err("connection closed".into()),
+    }
+    client.refresh().await?;
+    client.send(&Cmd::ListEpisodes, Pending::Episodes).await?;
+
+    let (tx, mut cmd_rx) = mpsc::channel::<Request>(32);
+    let episodes = Arc::new(Mutex::new(BTreeSet::new()));
+    let mut ui_state = UiState::new(tx, episodes.clone());
+    let mut remote = Remote {
+        snapshot: Snapshot::default(),
+    };
+    let mut terminal = Terminal::new(CrosstermBackend::new(stdout()))
+        .map_err(|err| format!("failed to initialize terminal: {err}"))?;
+    let mut ui_interval = time::interval(Duration::from_millis(100));
+
+    loop {
+        select! {
+            Some(ui_update) = ui_rx.recv() => {
+                ui_state.update(ui_update, &remote).await;
+                draw_ui(&mut terminal, &remote, &mut ui_state);
+            }
+            Some(Request { cmd, reply }) = cmd_rx.recv() => {
+                if cmd == Cmd::Shutdown {
+                    logln!("detaching");
+                    return Ok(());
+                }
+                client.send(&cmd, Pending::Reply(reply)).await?;
+            }
+            line = lines.next_line() => {
+                let Some(line) = line.map_err(io_err)? else {
+                    return Err("daemon closed the connection".into());
+                };
+                let Ok(value) = serde_json::from_str::<Value>(&line) else {
+                    logln!("unexpected line from daemon: {line}");
+                    continue;
+                };
+                if value.get("event").is_some() {
+                    match serde_json::from_value(value) {
+                        Ok(event) => on_event(event, &mut remote, &mut client).await?,
+                        Err(err) => logln!("unknown event: {err}"),
+                    }
+                } else {
+                    match serde_json::from_value(value) {
+                        Ok(response) => {
+                            on_response(response, &mut remote, &episodes, &mut client).await?
+                        }
+                        Err(err) => logln!("invalid response: {err}"),
+                    }
+                }
+            }
+            _ = ui_interval.tick() => {
+                draw_ui(&mut terminal, &remote, &mut ui_state);
+            }
+        }
+    }
+}
+
+async fn on_event<W: AsyncWrite + Unpin>(
+    event: Event,
+    remote: &mut Remote,
+    client: &mut Client<W>,
+) -> Result<(), String> {
+    let status = &mut remote.snapshot.status;
+    match event {
+        Event::Position {
+            uri,
+            position,
+            duration,
+        } => {
+            status.uri = Some(uri);
+            status.position = Some(position);
+            status.duration = duration;
+        }
+        Event::PlayState { state, playing } => {
+            status.state = state;
+            status.playing = playing;
+            // a listening session started or ended
+            client.refresh().await?;
+        }
+        Event::TrackChanged { .. }
+        | Event::QueueChanged { .. }
+        | Event::Finished { .. }
+        | Event::FeedRefreshing { .. } => {
+            client.refresh().await?;
+        }
+        Event::FeedRefreshed { .. } | Event::Unsubscribed { .. } => {
+            client.refresh().await?;
+            client.send(&Cmd::ListEpisodes, Pending::Episodes).await?;
+        }
+        // followed by `FeedRefreshed`
+        Event::NewEpisodes { .. } => {}
+        Event::Error { message } => logln!("daemon: {message}"),
+    }
+    Ok(())
+}
+
+async fn on_response<W: AsyncWrite + Unpin>(
+    response: RpcResponse,
+    remote: &mut Remote,
+    episodes: &Mutex<BTreeSet<Episode>>,
+    client: &mut Client<W>,
+) -> Result<(), String> {
+    let Some(pending) = response
+        .id
+        .as_u64()
+        .and_then(|id| client.pending.remove(&id))
+    else {
+        logln!("response to unknown request: {:?}", response.id);
+        return Ok(());
+    };
+    let response: Response = response.into_response();
+    if let Err(err) = &response {
+        logln!("{err}");
+    }
+    match (pending, response) {
+        (Pending::Reply(reply), response) => {
+            reply.send(response);
+            // not every command is followed by an event, like marking seen
+            client.refresh().await?;
+        }
+        (Pending::Snapshot, Ok(Some(value))) => match serde_json::from_value(value) {
+            Ok(snapshot) => remote.snapshot = snapshot,
+            Err(err) => logln!("invalid snapshot: {err}"),
+        },
+        (Pending::Episodes, Ok(Some(value))) => match serde_json::from_value(value) {
+            Ok(new) => {
+                if let Ok(mut episodes) = episodes.lock() {
+                    *episodes = new;
+                }
+            }
+            Err(err) => logln!("invalid episodes: {err}"),
+        },
+        _ => {}
+    }
+    if client.stale {
+        client.stale = false;
+        client.refresh().await?;
+    }
+    Ok(())
+}
@@ -0,0 +1,78 @@
+use ratatui::{
+    backend::Backend,
+    layout::{Constraint, Direction, Layout, Rect},
+    style::{Color, Style},
+    widgets::{Block, Borders, Cell, Paragraph, Row, Table},
+    Frame,
+};
+
+use crate::player::stats::{duration_string, Total};
+
+use super::view::View;
+
+pub fn draw_stats_tab<B: Backend>(f: &mut Frame<B>, player: &impl View) {
+    let chunks = Layout::default()
+        .direction(Direction::Vertical)
+        .margin(0)
+        .constraints(
+            [
+                Constraint::Length(2),
+                Constraint::Length(4),
+                Constraint::Min(3),
+            ]
+            .as_ref(),
+        )
+        .split(f.size());
+    let stats = player.stats();
+
+    let completion = stats
+        .completion
+        .map(|c| format!("{:.0}%", c * 100.0))
+        .unwrap_or_else(|| "n/a".into());
+    let summary = Paragraph::new(format!(
+        "listened {} in {} sessions\n{} episodes finished, {completion} average completion",
+        duration_string(stats.seconds),
+        stats.sessions,
+        stats.finished,
+    ))
+    .block(Block::default().borders(Borders::ALL).title("Stats"));
+    f.render_widget(summary, chunks[1]);
+
+    let columns = Layout::default()
+        .direction(Direction::Horizontal)
+        .constraints(
+            [
+                Constraint::Percentage(50),
+                Constraint::Percentage(25),
+                Constraint::Percentage(25),
+            ]
+            .as_ref(),
+        )
+        .split(chunks[2]);
+    draw_totals(f, columns[0], "Podcast", &stats.per_feed);
+    draw_totals(f, columns[1], "Day", &stats.per_day);
+    draw_totals(f, columns[2], "Week", &stats.per_week);
+}
+
+fn draw_totals<B: Backend>(f: &mut Frame<B>, chunk: Rect, title: &str, totals: &[Total]) {
+    let rows: Vec<Row> = totals
+        .iter()
+        .take(chunk.height.saturating_sub(3).into())
+        .map(|total| {
+            Row::new(vec![
+                Cell::from(total.name.clone()),
+                Cell::from(duration_string(total.seconds)),
+            ])
+        })
+        .collect();
+    let constraints = [
+        Constraint::Length(chunk.width.saturating_sub(12)),
+        Constraint::Length(8),
+    ];
+    let tbl = Table::new(rows)
+        .block(Block::default().borders(Borders::ALL))
+        .header(Row::new(vec![title, "Time"]).style(Style::default().fg(Color::Yellow)))
+        .widths(&constraints)
+        .column_spacing(1);
+    f.render_widget(tbl, chunk);
+}
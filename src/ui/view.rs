@@ -0,0 +1,89 @@
+use std::collections::{BTreeSet, VecDeque};
+
+use gstreamer::{ClockTime, State};
+
+use crate::player::{query::FeedInfo, state::Playable, stats::Stats, Player};
+
+/// What the interface draws from. Implemented by the [`Player`] itself when
+/// the interface runs in the same process and by
+/// [`Remote`](super::remote::Remote) when it is attached to a daemon.
+pub trait View {
+    fn queue(&self) -> &VecDeque<String>;
+    fn recent(&self) -> &VecDeque<String>;
+    fn playable(&self, uri: &str) -> Option<&Playable>;
+    fn current_uri(&self) -> Option<&str>;
+    fn position(&self) -> Option<ClockTime>;
+    fn duration(&self) -> Option<ClockTime>;
+    fn play_state(&self) -> &str;
+    fn feeds(&self) -> &[FeedInfo];
+    fn warning(&self) -> Option<&str>;
+    fn stats(&self) -> &Stats;
+    /// [Keys](crate::player::state::item_key) of the new episodes.
+    fn new_episodes(&self) -> &BTreeSet<String>;
+}
+
+/// The parts of the [`View`] of a [`Player`] that are costly to compute. They
+/// are updated when something changes instead of for every frame.
+#[derive(Debug, Default)]
+pub struct ViewCache {
+    pub feeds: Vec<FeedInfo>,
+    pub stats: Stats,
+    pub new_episodes: BTreeSet<String>,
+}
+
+impl View for Player {
+    fn queue(&self) -> &VecDeque<String> {
+        &self.state.queue
+    }
+
+    fn recent(&self) -> &VecDeque<String> {
+        &self.state.recent
+    }
+
+    fn playable(&self, uri: &str) -> Option<&Playable> {
+        self.state.uris.get(uri)
+    }
+
+    fn current_uri(&self) -> Option<&str> {
+        self.current_uri.as_deref()
+    }
+
+    fn position(&self) -> Option<ClockTime> {
+        self.query_position()
+    }
+
+    fn duration(&self) -> Option<ClockTime> {
+        self.duration
+    }
+
+    fn play_state(&self) -> &str {
+        state_to_str(self.play_state)
+    }
+
+    fn feeds(&self) -> &[FeedInfo] {
+        &self.view.feeds
+    }
+
+    fn warning(&self) -> Option<&str> {
+        self.state.warning.as_deref()
+    }
+
+    fn stats(&self) -> &Stats {
+        &self.view.stats
+    }
+
+    fn new_episodes(&self) -> &BTreeSet<String> {
+        &self.view.new_episodes
+    }
+}
+
+pub const fn state_to_str(state: State) -> &'static str {
+    match state {
+        State::VoidPending => "Void",
+        State::Null => "Null",
+        State::Ready => "Ready",
+        State::Paused => "Paused",
+        State::Playing => "Playing",
+        _ => "Unknown",
+    }
+}
@@ -0,0 +1,17 @@
+-- podaemon.db as written by schema version 1
+PRAGMA user_version = 1;
+CREATE TABLE meta (key TEXT PRIMARY KEY, value TEXT NOT NULL);
+CREATE TABLE feeds (uri TEXT PRIMARY KEY, title TEXT);
+CREATE TABLE episodes (uri TEXT PRIMARY KEY, title TEXT, album TEXT, source TEXT, length INTEGER);
+CREATE TABLE positions (uri TEXT PRIMARY KEY, progress INTEGER, updated INTEGER);
+CREATE TABLE queue (position INTEGER PRIMARY KEY, uri TEXT NOT NULL);
+CREATE TABLE history (position INTEGER PRIMARY KEY, uri TEXT NOT NULL);
+INSERT INTO meta VALUES ('json_imported', '1');
+INSERT INTO feeds VALUES ('https://feeds.example.com/show.rss', 'The Show');
+INSERT INTO feeds VALUES ('https://other.example.org/feed', NULL);
+INSERT INTO episodes VALUES ('https://cdn.example.com/show/12.mp3', 'Episode 12', 'The Show', 'https://feeds.example.com/show.rss', 3600);
+INSERT INTO episodes VALUES ('https://cdn.example.com/show/13.mp3', 'Episode 13', 'The Show', NULL, NULL);
+INSERT INTO positions VALUES ('https://cdn.example.com/show/12.mp3', 1830, 1700000000);
+INSERT INTO positions VALUES ('https://cdn.example.com/show/13.mp3', NULL, NULL);
+INSERT INTO queue VALUES (0, 'https://cdn.example.com/show/13.mp3');
+INSERT INTO history VALUES (0, 'https://cdn.example.com/show/12.mp3');
@@ -0,0 +1,23 @@
+-- podaemon.db as written by schema version 4
+PRAGMA user_version = 4;
+CREATE TABLE meta (key TEXT PRIMARY KEY, value TEXT NOT NULL);
+CREATE TABLE feeds (uri TEXT PRIMARY KEY, title TEXT);
+CREATE TABLE episodes (uri TEXT PRIMARY KEY, title TEXT, album TEXT, source TEXT, length INTEGER);
+CREATE TABLE positions (uri TEXT PRIMARY KEY, progress INTEGER, updated INTEGER, finished INTEGER, play_count INTEGER NOT NULL DEFAULT 0);
+CREATE TABLE queue (position INTEGER PRIMARY KEY, uri TEXT NOT NULL);
+CREATE TABLE history (position INTEGER PRIMARY KEY, uri TEXT NOT NULL);
+INSERT INTO meta VALUES ('json_imported', '1');
+INSERT INTO feeds VALUES ('https://feeds.example.com/show.rss', 'The Show');
+INSERT INTO feeds VALUES ('https://other.example.org/feed', NULL);
+INSERT INTO episodes VALUES ('https://cdn.example.com/show/12.mp3', 'Episode 12', 'The Show', 'https://feeds.example.com/show.rss', 3600);
+INSERT INTO episodes VALUES ('https://cdn.example.com/show/13.mp3', 'Episode 13', 'The Show', NULL, NULL);
+INSERT INTO positions VALUES ('https://cdn.example.com/show/12.mp3', 1830, 1700000000, 1690000000, 1);
+INSERT INTO positions VALUES ('https://cdn.example.com/show/13.mp3', NULL, NULL, NULL, 0);
+INSERT INTO queue VALUES (0, 'https://cdn.example.com/show/13.mp3');
+INSERT INTO history VALUES (0, 'https://cdn.example.com/show/12.mp3');
+CREATE TABLE sessions (uri TEXT NOT NULL, feed TEXT, started INTEGER NOT NULL, stopped INTEGER NOT NULL, start_pos INTEGER, end_pos INTEGER);
+CREATE INDEX sessions_started ON sessions (started);
+INSERT INTO sessions VALUES ('https://cdn.example.com/show/12.mp3', 'The Show', 1689996400, 1690000000, 0, 3600);
+INSERT INTO sessions VALUES ('https://cdn.example.com/show/12.mp3', 'The Show', 1699998170, 1700000000, 0, 1830);
+CREATE TABLE new_episodes (key TEXT PRIMARY KEY);
+INSERT INTO new_episodes VALUES ('https://feeds.example.com/show.rss#14');